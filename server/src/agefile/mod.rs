@@ -0,0 +1,110 @@
+//! Structural validation of uploaded ciphertexts.
+//!
+//! Only the age header is parsed. It names the recipient types a file key is
+//! wrapped for, so no private key is needed, but the recipients themselves
+//! stay opaque.
+
+use age::armor::ArmoredReader;
+use std::io::Read;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Bytes read from the start of a body to find its header; generous for a
+/// handful of recipients, even post-quantum ones.
+pub const MAX_HEADER_BYTES: u64 = 128 * 1024;
+
+const VERSION_LINE: &[u8] = b"age-encryption.org/v1";
+const ARMOR_BEGIN: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+const BODY_COLUMNS: usize = 64;
+const MAC_LEN: usize = 43; // unpadded base64 of an HMAC-SHA256
+
+/// How an age file is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgeFormat {
+    Binary,
+    Armored, // ASCII armor (`age -a`)
+}
+
+impl AgeFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgeFormat::Binary => "binary",
+            AgeFormat::Armored => "armored",
+        }
+    }
+}
+
+/// The parts of an age header relevant to validation.
+#[derive(Debug)]
+pub struct AgeHeader {
+    pub format: AgeFormat,
+    pub stanza_types: Vec<String>, // in header order, grease included
+}
+
+/// Parse the header at the start of `prefix`, which may end anywhere after it.
+pub fn parse_header(prefix: &[u8]) -> Result<AgeHeader, &'static str> {
+    if !prefix.starts_with(ARMOR_BEGIN) {
+        return Ok(AgeHeader {
+            format: AgeFormat::Binary,
+            stanza_types: stanza_types(prefix)?,
+        });
+    }
+    // A truncated armor fails once its end is reached, by which point the
+    // header has been decoded if it fits in the prefix
+    let mut reader = ArmoredReader::new(prefix);
+    let mut decoded = Vec::new();
+    let mut buf = [0; 4096];
+    while let Ok(n @ 1..) = reader.read(&mut buf) {
+        decoded.extend_from_slice(&buf[..n]);
+    }
+    Ok(AgeHeader {
+        format: AgeFormat::Armored,
+        stanza_types: stanza_types(&decoded)?,
+    })
+}
+
+/// The first [`MAX_HEADER_BYTES`] of the file at `path`, for [`parse_header`].
+pub async fn read_prefix(path: &Path) -> std::io::Result<Vec<u8>> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut prefix = Vec::new();
+    file.take(MAX_HEADER_BYTES).read_to_end(&mut prefix).await?;
+    Ok(prefix)
+}
+
+/// Types of the recipient stanzas in a binary header, which must be complete.
+fn stanza_types(header: &[u8]) -> Result<Vec<String>, &'static str> {
+    const MALFORMED: &str = "Malformed or oversized age header";
+    let mut lines = header.split(|&b| b == b'\n');
+    if lines.next() != Some(VERSION_LINE) {
+        return Err("Body is not an age v1 file");
+    }
+    let mut types = Vec::new();
+    loop {
+        let line = lines.next().ok_or(MALFORMED)?;
+        if let Some(args) = line.strip_prefix(b"-> ") {
+            let stanza_type = args.split(|&b| b == b' ').next().unwrap_or_default();
+            if stanza_type.is_empty() {
+                return Err(MALFORMED);
+            }
+            types.push(String::from_utf8(stanza_type.to_vec()).map_err(|_| MALFORMED)?);
+            // The body is wrapped at 64 columns and ends with a shorter line
+            loop {
+                match lines.next().ok_or(MALFORMED)?.len() {
+                    BODY_COLUMNS => continue,
+                    len if len < BODY_COLUMNS => break,
+                    _ => return Err(MALFORMED),
+                }
+            }
+        } else if let Some(mac) = line.strip_prefix(b"--- ") {
+            if mac.len() != MAC_LEN || types.is_empty() {
+                return Err(MALFORMED);
+            }
+            return Ok(types);
+        } else {
+            return Err(MALFORMED);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
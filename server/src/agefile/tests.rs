@@ -0,0 +1,79 @@
+use super::*;
+use age::armor::{ArmoredWriter, Format};
+use age::{Encryptor, Recipient, x25519};
+use std::io::Write;
+
+/// Encrypt `plaintext` to `recipients`, ASCII-armored if `armored`.
+pub(crate) fn encrypt(recipients: &[&dyn Recipient], plaintext: &[u8], armored: bool) -> Vec<u8> {
+    let format = if armored {
+        Format::AsciiArmor
+    } else {
+        Format::Binary
+    };
+    let encryptor = Encryptor::with_recipients(recipients.iter().copied()).unwrap();
+    let output = ArmoredWriter::wrap_output(vec![], format).unwrap();
+    let mut writer = encryptor.wrap_output(output).unwrap();
+    writer.write_all(plaintext).unwrap();
+    writer.finish().unwrap().finish().unwrap()
+}
+
+#[test]
+fn test_parse_binary_and_armored_headers() {
+    let recipient = x25519::Identity::generate().to_public();
+    let binary = encrypt(&[&recipient], b"hello", false);
+    let header = parse_header(&binary).unwrap();
+    assert_eq!(header.format, AgeFormat::Binary);
+    assert!(header.stanza_types.iter().any(|t| t == "X25519"));
+
+    let armored = encrypt(&[&recipient], b"hello", true);
+    assert!(armored.starts_with(ARMOR_BEGIN));
+    let header = parse_header(&armored).unwrap();
+    assert_eq!(header.format, AgeFormat::Armored);
+    assert!(header.stanza_types.iter().any(|t| t == "X25519"));
+}
+
+#[test]
+fn test_parse_lists_every_stanza() {
+    let ssh = crate::mailbox::tests::TEST_SSH_ED25519_PUB
+        .parse::<age::ssh::Recipient>()
+        .unwrap();
+    let x25519 = x25519::Identity::generate().to_public();
+    let ciphertext = encrypt(&[&ssh, &x25519], &[0; 100_000], false);
+    let types = parse_header(&ciphertext).unwrap().stanza_types;
+    assert!(types.iter().any(|t| t == "ssh-ed25519"));
+    assert!(types.iter().any(|t| t == "X25519"));
+}
+
+#[test]
+fn test_header_only_prefix_is_enough() {
+    let recipient = x25519::Identity::generate().to_public();
+    for armored in [false, true] {
+        let ciphertext = encrypt(&[&recipient], &[0; 300_000], armored);
+        let prefix = &ciphertext[..MAX_HEADER_BYTES as usize];
+        assert!(parse_header(prefix).is_ok());
+    }
+}
+
+#[test]
+fn test_parse_rejects_non_age_bodies() {
+    let recipient = x25519::Identity::generate().to_public();
+    let ciphertext = encrypt(&[&recipient], b"hello", false);
+    let armored = encrypt(&[&recipient], b"hello", true);
+    let mac_line = ciphertext.windows(4).position(|w| w == b"\n---").unwrap();
+    let no_stanza = b"age-encryption.org/v1\n--- AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n";
+    for invalid in [
+        &b""[..],
+        b"plaintext that was never encrypted",
+        b"age-encryption.org/v2\n",
+        &ciphertext[..mac_line + 10],
+        &armored[..ARMOR_BEGIN.len() + 20],
+        no_stanza,
+        b"age-encryption.org/v1\n-> \n\n--- AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n",
+        b"-----BEGIN AGE ENCRYPTED FILE-----\nnot base64!\n",
+    ] {
+        assert!(parse_header(invalid).is_err(), "{:?}", invalid);
+    }
+    // The same structure with a stanza is accepted
+    let minimal = b"age-encryption.org/v1\n-> X25519 arg\nYm9keQ\n--- AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n";
+    assert_eq!(parse_header(minimal).unwrap().stanza_types, ["X25519"]);
+}
@@ -1,13 +1,14 @@
-use crate::config::Config;
-use age::{Encryptor, Recipient, x25519};
+use crate::config::{AppState, Config};
+use crate::mailbox::MailboxId;
+use crate::replay;
+use age::{Encryptor, Recipient};
 use axum::http::StatusCode;
-use axum_extra::headers::Authorization;
 use base64::Engine;
-use chrono::{Duration, Utc};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::Validation;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::io::Write;
+use uuid::Uuid;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthClaims {
@@ -16,6 +17,9 @@ pub struct AuthClaims {
     pub exp: i64,
     pub iat: i64,
     pub telegram: Option<String>,
+    // Unique per token, for replay protection; older tokens may lack it
+    #[serde(default)]
+    pub jti: Option<Uuid>,
 }
 
 impl AuthClaims {
@@ -26,42 +30,71 @@ impl AuthClaims {
             exp,
             iat,
             telegram,
+            jti: Some(Uuid::new_v4()),
         }
     }
 }
 
+/// Verify a bearer token for `expected_aud`. Tokens of single-use scopes are
+/// rejected once they have been presented before, or if they carry no `jti`.
 pub async fn verify_jwt_from_header(
     jwt: &str,
-    config: &Config,
+    state: &AppState,
     expected_aud: &str,
 ) -> Result<AuthClaims, (StatusCode, String)> {
-    let mut validation = Validation::new(Algorithm::HS256);
+    let config = &state.config;
+    let mut validation = Validation::default();
     validation.set_audience(&[expected_aud]);
-    let token_data = decode::<AuthClaims>(
-        jwt,
-        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-        &validation,
-    )
-    .map_err(|e| (StatusCode::UNAUTHORIZED, format!("JWT error: {}", e)))?;
+    let token_data = config
+        .keyring
+        .decode::<AuthClaims>(jwt, &validation)
+        .map_err(|e| (StatusCode::UNAUTHORIZED, format!("JWT error: {}", e)))?;
     // Check expiration
     let now = chrono::Utc::now().timestamp();
     if token_data.claims.exp < now {
         return Err((StatusCode::UNAUTHORIZED, "JWT expired".to_string()));
     }
+    let scope = expected_aud.trim_start_matches('/');
+    if replay::is_single_use(config, scope) {
+        let claims = &token_data.claims;
+        let Some(jti) = claims.jti else {
+            return Err((StatusCode::UNAUTHORIZED, "JWT has no jti".to_string()));
+        };
+        let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_default();
+        match state.seen_tokens.first_use(jti, expires_at).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err((StatusCode::UNAUTHORIZED, "JWT already used".to_string()));
+            }
+            Err(e) => {
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Replay store error: {}", e),
+                ));
+            }
+        }
+    }
     Ok(token_data.claims)
 }
 
+/// Subject of `jwt` if it is validly signed and unexpired, whatever its audience.
+/// Used to attribute requests to a mailbox before the handler checks the scope.
+pub fn verified_subject(jwt: &str, config: &Config) -> Option<String> {
+    let mut validation = Validation::default();
+    validation.validate_aud = false;
+    config
+        .keyring
+        .decode::<AuthClaims>(jwt, &validation)
+        .ok()
+        .map(|token_data| token_data.claims.sub)
+}
+
 /// Create and sign a JWT for the challenge
 pub fn create_challenge_jwt(
     claims: &AuthClaims,
     config: &Config,
 ) -> Result<String, (StatusCode, String)> {
-    encode(
-        &Header::default(),
-        claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
-    )
-    .map_err(|e| {
+    config.keyring.encode(claims).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("JWT error: {}", e),
@@ -69,15 +102,17 @@ pub fn create_challenge_jwt(
     })
 }
 
-/// Encrypt a JWT with age for the given recipient public key (base64)
+/// Encrypt a JWT with age for the given recipient public key (X25519, SSH or,
+/// with the `pq` feature, ML-KEM-768 + X25519)
 pub fn encrypt_jwt_for_recipient(
     jwt: &str,
-    recipient_pubkey_b64: &str,
+    recipient_pubkey: &str,
 ) -> Result<String, (StatusCode, String)> {
-    let recipient = recipient_pubkey_b64
-        .parse::<x25519::Recipient>()
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid pubkey: {}", e)))?;
-    let recipients: Vec<Box<dyn Recipient + Send>> = vec![Box::new(recipient)];
+    let recipient = recipient_pubkey
+        .parse::<MailboxId>()
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid pubkey: {}", e)))?
+        .recipient();
+    let recipients: Vec<Box<dyn Recipient + Send>> = vec![recipient];
     let encryptor =
         Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn Recipient))
             .map_err(|e| {
@@ -115,7 +150,7 @@ pub fn build_and_encrypt_challenge_jwt(
     aud: &str,
     telegram: Option<&str>,
     config: &Config,
-    recipient_pubkey_b64: &str,
+    recipient_pubkey: &str,
     ttl_secs: i64,
 ) -> Result<String, (StatusCode, String)> {
     let now = Utc::now().timestamp();
@@ -126,9 +161,10 @@ pub fn build_and_encrypt_challenge_jwt(
         exp,
         iat: now,
         telegram: telegram.map(|s| s.to_string()),
+        jti: Some(Uuid::new_v4()),
     };
     let jwt = create_challenge_jwt(&claims, config)?;
-    encrypt_jwt_for_recipient(&jwt, recipient_pubkey_b64)
+    encrypt_jwt_for_recipient(&jwt, recipient_pubkey)
 }
 
 #[cfg(test)]
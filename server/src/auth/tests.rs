@@ -1,24 +1,24 @@
 use super::*;
+use crate::config::{test_config, test_state};
 use age::{Decryptor, Identity, x25519};
-use base64::{engine::general_purpose::URL_SAFE, read};
+use base64::engine::general_purpose::URL_SAFE;
 use chrono::Utc;
-use serde::de;
 
-fn test_config() -> Config {
-    Config {
-        host: "127.0.0.1".to_string(),
-        port: 12345,
-        database_url: "sqlite::memory:".to_string(),
-        jwt_secret: "test_secret_1234567890".to_string(),
-        jwt_expiration_seconds: 60,
-        retrieve_page_size: 10,
-        database_schema_version: 0,
-    }
+fn sign(config: &Config, aud: &str) -> String {
+    let claims = AuthClaims::new(
+        "test_pubkey".to_string(),
+        aud.to_string(),
+        Utc::now().timestamp() + 60,
+        Utc::now().timestamp(),
+        None,
+    );
+    create_challenge_jwt(&claims, config).unwrap()
 }
 
 #[test]
 fn test_jwt_create_and_verify() {
     let config = test_config();
+    let state = test_state(config.clone());
     let claims = AuthClaims::new(
         "test_pubkey".to_string(),
         "/retrieve".to_string(),
@@ -29,7 +29,7 @@ fn test_jwt_create_and_verify() {
     let jwt = create_challenge_jwt(&claims, &config).unwrap();
     let rt = tokio::runtime::Runtime::new().unwrap();
     let verified = rt
-        .block_on(verify_jwt_from_header(&jwt, &config, "/retrieve"))
+        .block_on(verify_jwt_from_header(&jwt, &state, "/retrieve"))
         .unwrap();
     assert_eq!(verified.sub, claims.sub);
     assert_eq!(verified.aud, claims.aud);
@@ -53,3 +53,98 @@ fn test_age_encrypt_decrypt() {
     std::io::copy(&mut reader, &mut out).unwrap();
     assert_eq!(msg.as_bytes(), &out[..]);
 }
+
+#[test]
+fn test_age_encrypt_for_ssh_recipient() {
+    use crate::mailbox::tests::{TEST_SSH_ED25519_PUB, ssh_identity};
+    let msg = "hello ssh!";
+    let ciphertext_b64 = encrypt_jwt_for_recipient(msg, TEST_SSH_ED25519_PUB).unwrap();
+    let ciphertext = URL_SAFE.decode(ciphertext_b64).unwrap();
+    let identity = ssh_identity();
+    let decryptor = Decryptor::new(&ciphertext[..]).unwrap();
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&identity as &dyn Identity))
+        .unwrap();
+    let mut out = Vec::new();
+    std::io::copy(&mut reader, &mut out).unwrap();
+    assert_eq!(msg.as_bytes(), &out[..]);
+}
+
+#[tokio::test]
+async fn test_single_use_scopes() {
+    let mut config = test_config();
+    config.single_use_scopes = vec!["delete".to_string()];
+    let state = test_state(config.clone());
+    // Reusable until it expires
+    let jwt = sign(&config, "/retrieve");
+    for _ in 0..2 {
+        assert!(
+            verify_jwt_from_header(&jwt, &state, "/retrieve")
+                .await
+                .is_ok()
+        );
+    }
+    let jwt = sign(&config, "/delete");
+    assert!(
+        verify_jwt_from_header(&jwt, &state, "/delete")
+            .await
+            .is_ok()
+    );
+    let (status, _) = verify_jwt_from_header(&jwt, &state, "/delete")
+        .await
+        .unwrap_err();
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    // Every token gets its own jti
+    let other = sign(&config, "/delete");
+    assert!(
+        verify_jwt_from_header(&other, &state, "/delete")
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn test_tokens_without_jti() {
+    let mut config = test_config();
+    config.single_use_scopes = vec!["delete".to_string()];
+    let state = test_state(config.clone());
+    // Tokens issued before jti was added
+    let token = |aud: &str| {
+        let claims = serde_json::json!({
+            "sub": "test_pubkey",
+            "aud": aud,
+            "exp": Utc::now().timestamp() + 60,
+            "iat": Utc::now().timestamp(),
+        });
+        config.keyring.encode(&claims).unwrap()
+    };
+    // Reusable scopes do not need a jti
+    let jwt = token("/retrieve");
+    let claims = verify_jwt_from_header(&jwt, &state, "/retrieve")
+        .await
+        .unwrap();
+    assert_eq!(claims.jti, None);
+    // Single-use scopes cannot be tracked without one
+    let (status, _) = verify_jwt_from_header(&token("/delete"), &state, "/delete")
+        .await
+        .unwrap_err();
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[cfg(feature = "pq")]
+#[test]
+fn test_age_encrypt_for_pq_recipient() {
+    use crate::pq::tests::TestIdentity;
+    let identity = TestIdentity::generate();
+    let pubkey = identity.to_public().to_string();
+    let msg = "hello post-quantum!";
+    let ciphertext_b64 = encrypt_jwt_for_recipient(msg, &pubkey).unwrap();
+    let ciphertext = URL_SAFE.decode(ciphertext_b64).unwrap();
+    let decryptor = Decryptor::new(&ciphertext[..]).unwrap();
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&identity as &dyn Identity))
+        .unwrap();
+    let mut out = Vec::new();
+    std::io::copy(&mut reader, &mut out).unwrap();
+    assert_eq!(msg.as_bytes(), &out[..]);
+}
@@ -0,0 +1,106 @@
+use super::{BlobReader, BlobStore, StagedBlob, content_key};
+use crate::error::BlobError;
+use async_trait::async_trait;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use uuid::Uuid;
+
+/// Blob store backed by a local directory.
+///
+/// Blobs are sharded by the first two hex digits of their key
+/// (`<root>/ab/abcdef...`) to keep directories small.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        LocalBlobStore {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf, BlobError> {
+        // Keys are hex digests; reject anything else so a key can never escape `root`
+        if key.len() < 2 || !key.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(BlobError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, data: &[u8]) -> Result<String, BlobError> {
+        let key = content_key(data);
+        let path = self.path_for(&key)?;
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(key);
+        }
+        let dir = path.parent().expect("blob path has a parent");
+        tokio::fs::create_dir_all(dir).await?;
+        // Write to a temporary file first so readers never observe a partial blob
+        let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(key)
+    }
+
+    async fn put_staged(&self, staged: StagedBlob) -> Result<String, BlobError> {
+        let key = staged.key().to_string();
+        let path = self.path_for(&key)?;
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(key);
+        }
+        let dir = path.parent().expect("blob path has a parent");
+        tokio::fs::create_dir_all(dir).await?;
+        // Rename when the staging dir is on the same filesystem, copy otherwise
+        if tokio::fs::rename(staged.path(), &path).await.is_err() {
+            let tmp = dir.join(format!(".{}.tmp", Uuid::new_v4()));
+            tokio::fs::copy(staged.path(), &tmp).await?;
+            tokio::fs::rename(&tmp, &path).await?;
+        }
+        Ok(key)
+    }
+
+    async fn open(&self, key: &str) -> Result<Option<BlobReader>, BlobError> {
+        let file = match tokio::fs::File::open(self.path_for(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let size = file.metadata().await?.len();
+        Ok(Some(BlobReader {
+            size,
+            stream: Box::pin(tokio_util::io::ReaderStream::new(file)),
+        }))
+    }
+
+    async fn open_range(
+        &self,
+        key: &str,
+        range: Range<u64>,
+    ) -> Result<Option<BlobReader>, BlobError> {
+        let mut file = match tokio::fs::File::open(self.path_for(key)?).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let size = range.end - range.start;
+        Ok(Some(BlobReader {
+            size,
+            stream: Box::pin(tokio_util::io::ReaderStream::new(file.take(size))),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        match tokio::fs::remove_file(self.path_for(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use super::{BlobReader, BlobStore, StagedBlob, content_key};
+use crate::error::BlobError;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// Non-persistent blob store keeping everything in process memory.
+#[derive(Default)]
+pub struct MemoryBlobStore {
+    blobs: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemoryBlobStore {
+    async fn put(&self, data: &[u8]) -> Result<String, BlobError> {
+        let key = content_key(data);
+        self.blobs
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| data.to_vec());
+        Ok(key)
+    }
+
+    async fn put_staged(&self, staged: StagedBlob) -> Result<String, BlobError> {
+        let data = tokio::fs::read(staged.path()).await?;
+        self.blobs
+            .lock()
+            .unwrap()
+            .entry(staged.key().to_string())
+            .or_insert(data);
+        Ok(staged.key().to_string())
+    }
+
+    async fn open(&self, key: &str) -> Result<Option<BlobReader>, BlobError> {
+        let data = self.blobs.lock().unwrap().get(key).cloned();
+        Ok(data.map(|data| BlobReader {
+            size: data.len() as u64,
+            stream: Box::pin(futures_util::stream::once(async { Ok(Bytes::from(data)) })),
+        }))
+    }
+
+    async fn open_range(
+        &self,
+        key: &str,
+        range: Range<u64>,
+    ) -> Result<Option<BlobReader>, BlobError> {
+        let blobs = self.blobs.lock().unwrap();
+        let Some(data) = blobs.get(key) else {
+            return Ok(None);
+        };
+        let part = data
+            .get(range.start as usize..range.end as usize)
+            .ok_or_else(|| BlobError::Io(std::io::ErrorKind::UnexpectedEof.into()))?;
+        let part = Bytes::copy_from_slice(part);
+        Ok(Some(BlobReader {
+            size: part.len() as u64,
+            stream: Box::pin(futures_util::stream::once(async { Ok(part) })),
+        }))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        self.blobs.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
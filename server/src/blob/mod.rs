@@ -0,0 +1,147 @@
+pub mod local;
+pub mod memory;
+pub mod s3;
+pub mod staging;
+
+use crate::config::Config;
+use crate::error::{BlobError, StorageError};
+use crate::storage::Storage;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::stream::BoxStream;
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub use local::LocalBlobStore;
+pub use memory::MemoryBlobStore;
+pub use s3::S3BlobStore;
+pub use staging::StagedBlob;
+
+/// Stream of body chunks read from a blob store.
+pub type ByteStream = BoxStream<'static, Result<Bytes, std::io::Error>>;
+
+/// An open blob: its total size and a stream over its bytes.
+pub struct BlobReader {
+    pub size: u64,
+    pub stream: ByteStream,
+}
+
+/// Content address of a blob: lowercase hex SHA-256 of its bytes.
+pub fn content_key(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Content-addressed store for ciphertext bodies.
+///
+/// Item metadata lives in [`Storage`]; each item only keeps the key returned
+/// by [`BlobStore::put`] or [`BlobStore::put_staged`]. Identical bodies share
+/// one blob.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Store `data` and return its content key. Storing an existing blob is a no-op.
+    async fn put(&self, data: &[u8]) -> Result<String, BlobError>;
+
+    /// Store a body already spooled to disk, without loading it into memory.
+    async fn put_staged(&self, staged: StagedBlob) -> Result<String, BlobError>;
+
+    /// Open a blob for streaming, or `None` if it does not exist.
+    async fn open(&self, key: &str) -> Result<Option<BlobReader>, BlobError>;
+
+    /// Open only the bytes in `range`, which the caller has checked against
+    /// the blob size. The reader's `size` is the length of the range.
+    async fn open_range(
+        &self,
+        key: &str,
+        range: Range<u64>,
+    ) -> Result<Option<BlobReader>, BlobError>;
+
+    /// Remove a blob. Deleting a missing blob is not an error.
+    async fn delete(&self, key: &str) -> Result<(), BlobError>;
+}
+
+/// Build the blob store selected by `BLOB_BACKEND` (`local`, `s3` or `memory`).
+pub fn from_config(
+    config: &Config,
+    http_client: &reqwest::Client,
+) -> Result<Arc<dyn BlobStore>, BlobError> {
+    match config.blob_backend.as_str() {
+        "local" => Ok(Arc::new(LocalBlobStore::new(&config.blob_dir))),
+        "s3" => Ok(Arc::new(S3BlobStore::from_config(
+            config,
+            http_client.clone(),
+        )?)),
+        "memory" => Ok(Arc::new(MemoryBlobStore::new())),
+        other => Err(BlobError::Config(format!(
+            "Unknown blob backend: {}",
+            other
+        ))),
+    }
+}
+
+/// Directory where incoming bodies are spooled before entering the blob store.
+///
+/// Defaults to a subdirectory of `BLOB_DIR` for the local backend, so staged
+/// files can be renamed into place, and to the system temp dir otherwise.
+pub fn staging_dir(config: &Config) -> PathBuf {
+    match &config.upload_staging_dir {
+        Some(dir) => PathBuf::from(dir),
+        None if config.blob_backend == "local" => PathBuf::from(&config.blob_dir).join(".staging"),
+        None => std::env::temp_dir().join("deadrop-staging"),
+    }
+}
+
+/// Move ciphertexts still stored inline in the `items` table into the blob
+/// store, returning the number of rows migrated.
+pub async fn migrate_inline_ciphertexts(
+    storage: &dyn Storage,
+    blobs: &dyn BlobStore,
+) -> Result<u64, StorageError> {
+    let mut migrated = 0;
+    loop {
+        let batch = storage.inline_ciphertexts(100).await?;
+        if batch.is_empty() {
+            return Ok(migrated);
+        }
+        for (id, ciphertext) in batch {
+            let key = blobs.put(&ciphertext).await?;
+            storage
+                .set_blob_ref(id, &key, ciphertext.len() as i64)
+                .await?;
+            migrated += 1;
+        }
+    }
+}
+
+/// Delete an item and, unless another item shares its content, its blob.
+/// Returns whether the item existed.
+///
+/// An identical upload racing with the delete can lose its blob between
+/// `put` and `insert_item`; senders can simply upload again.
+pub async fn delete_item_and_blob(
+    storage: &dyn Storage,
+    blobs: &dyn BlobStore,
+    item: &crate::db::DbItem,
+) -> Result<bool, StorageError> {
+    if !storage.delete_item(item.id).await? {
+        return Ok(false);
+    }
+    delete_unreferenced_blob(storage, blobs, &item.blob_key).await?;
+    Ok(true)
+}
+
+/// Delete a blob once no item refers to it any more.
+pub async fn delete_unreferenced_blob(
+    storage: &dyn Storage,
+    blobs: &dyn BlobStore,
+    blob_key: &str,
+) -> Result<(), StorageError> {
+    if storage.count_blob_refs(blob_key).await? == 0 {
+        blobs.delete(blob_key).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
@@ -0,0 +1,264 @@
+use super::{BlobReader, BlobStore, StagedBlob, content_key};
+use crate::config::Config;
+use crate::error::BlobError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// SHA-256 of the empty payload, used for requests without a body.
+const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Blob store backed by an S3-compatible object storage API (AWS S3, MinIO, ...).
+///
+/// Uses path-style URLs (`<endpoint>/<bucket>/<key>`) and AWS Signature
+/// Version 4 request signing.
+pub struct S3BlobStore {
+    client: reqwest::Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl S3BlobStore {
+    pub fn from_config(config: &Config, client: reqwest::Client) -> Result<Self, BlobError> {
+        let required = |value: &Option<String>, name: &str| {
+            value.clone().ok_or_else(|| {
+                BlobError::Config(format!("{} is required for the s3 backend", name))
+            })
+        };
+        Ok(S3BlobStore {
+            client,
+            endpoint: required(&config.s3_endpoint, "S3_ENDPOINT")?
+                .trim_end_matches('/')
+                .to_string(),
+            bucket: required(&config.s3_bucket, "S3_BUCKET")?,
+            region: config.s3_region.clone(),
+            access_key_id: required(&config.s3_access_key_id, "S3_ACCESS_KEY_ID")?,
+            secret_access_key: required(&config.s3_secret_access_key, "S3_SECRET_ACCESS_KEY")?,
+        })
+    }
+
+    /// Build a signed request for `key`; `payload_sha256` is the hex digest of the body.
+    fn request(
+        &self,
+        method: Method,
+        key: &str,
+        payload_sha256: &str,
+    ) -> Result<reqwest::RequestBuilder, BlobError> {
+        if !key.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(BlobError::InvalidKey(key.to_string()));
+        }
+        let path = format!("/{}/{}", self.bucket, key);
+        let url = reqwest::Url::parse(&format!("{}{}", self.endpoint, path))
+            .map_err(|e| BlobError::Config(format!("Invalid S3 endpoint: {}", e)))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = sign_v4(&SigningParams {
+            method: method.as_str(),
+            host: &host,
+            canonical_uri: url.path(),
+            extra_headers: &[],
+            payload_sha256,
+            now,
+            region: &self.region,
+            access_key_id: &self.access_key_id,
+            secret_access_key: &self.secret_access_key,
+        });
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_sha256)
+            .header("x-amz-date", amz_date)
+            .header(reqwest::header::AUTHORIZATION, authorization))
+    }
+}
+
+fn http_error(e: reqwest::Error) -> BlobError {
+    BlobError::Http(e.to_string())
+}
+
+fn status_error(status: StatusCode) -> BlobError {
+    BlobError::Http(format!("S3 returned {}", status))
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, data: &[u8]) -> Result<String, BlobError> {
+        // The content key is the payload SHA-256, so it doubles as the signed payload hash
+        let key = content_key(data);
+        let resp = self
+            .request(Method::PUT, &key, &key)?
+            .body(data.to_vec())
+            .send()
+            .await
+            .map_err(http_error)?;
+        if !resp.status().is_success() {
+            return Err(status_error(resp.status()));
+        }
+        Ok(key)
+    }
+
+    async fn put_staged(&self, staged: StagedBlob) -> Result<String, BlobError> {
+        let key = staged.key().to_string();
+        let file = tokio::fs::File::open(staged.path()).await?;
+        let resp = self
+            .request(Method::PUT, &key, &key)?
+            .header(reqwest::header::CONTENT_LENGTH, staged.size())
+            .body(reqwest::Body::wrap_stream(
+                tokio_util::io::ReaderStream::new(file),
+            ))
+            .send()
+            .await
+            .map_err(http_error)?;
+        if !resp.status().is_success() {
+            return Err(status_error(resp.status()));
+        }
+        Ok(key)
+    }
+
+    async fn open(&self, key: &str) -> Result<Option<BlobReader>, BlobError> {
+        let resp = self
+            .request(Method::GET, key, EMPTY_PAYLOAD_SHA256)?
+            .send()
+            .await
+            .map_err(http_error)?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            // Downloads announce the size as Content-Length, so it must be known
+            s if s.is_success() => match resp.content_length() {
+                Some(size) => Ok(Some(BlobReader {
+                    size,
+                    stream: Box::pin(resp.bytes_stream().map_err(std::io::Error::other)),
+                })),
+                None => Err(BlobError::Http(
+                    "S3 response has no Content-Length".to_string(),
+                )),
+            },
+            s => Err(status_error(s)),
+        }
+    }
+
+    async fn open_range(
+        &self,
+        key: &str,
+        range: Range<u64>,
+    ) -> Result<Option<BlobReader>, BlobError> {
+        let size = range.end - range.start;
+        let resp = self
+            .request(Method::GET, key, EMPTY_PAYLOAD_SHA256)?
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            )
+            .send()
+            .await
+            .map_err(http_error)?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            // A plain 200 is only correct if the range happened to be the whole object
+            s if s == StatusCode::PARTIAL_CONTENT
+                || (s.is_success() && resp.content_length() == Some(size)) =>
+            {
+                Ok(Some(BlobReader {
+                    size,
+                    stream: Box::pin(resp.bytes_stream().map_err(std::io::Error::other)),
+                }))
+            }
+            s => Err(status_error(s)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        let resp = self
+            .request(Method::DELETE, key, EMPTY_PAYLOAD_SHA256)?
+            .send()
+            .await
+            .map_err(http_error)?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            s if s.is_success() => Ok(()),
+            s => Err(status_error(s)),
+        }
+    }
+}
+
+pub(crate) struct SigningParams<'a> {
+    pub method: &'a str,
+    pub host: &'a str,
+    pub canonical_uri: &'a str,
+    /// Additional signed headers as lowercase `(name, value)` pairs.
+    pub extra_headers: &'a [(&'a str, &'a str)],
+    pub payload_sha256: &'a str,
+    pub now: DateTime<Utc>,
+    pub region: &'a str,
+    pub access_key_id: &'a str,
+    pub secret_access_key: &'a str,
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Compute the AWS Signature Version 4 `Authorization` header value for an S3
+/// request without a query string. `host`, `x-amz-content-sha256` and
+/// `x-amz-date` are always signed.
+pub(crate) fn sign_v4(params: &SigningParams) -> String {
+    let amz_date = params.now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = params.now.format("%Y%m%d").to_string();
+    let mut headers: Vec<(&str, &str)> = vec![
+        ("host", params.host),
+        ("x-amz-content-sha256", params.payload_sha256),
+        ("x-amz-date", &amz_date),
+    ];
+    headers.extend_from_slice(params.extra_headers);
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        params.method,
+        params.canonical_uri,
+        canonical_headers,
+        signed_headers,
+        params.payload_sha256
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, params.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let k_date = hmac_sha256(
+        format!("AWS4{}", params.secret_access_key).as_bytes(),
+        &date,
+    );
+    let k_region = hmac_sha256(&k_date, params.region);
+    let k_service = hmac_sha256(&k_region, "s3");
+    let k_signing = hmac_sha256(&k_service, "aws4_request");
+    let signature = hex::encode(hmac_sha256(&k_signing, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        params.access_key_id, scope, signed_headers, signature
+    )
+}
@@ -0,0 +1,99 @@
+use crate::error::BlobError;
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// A body spooled to a temporary file while its content key was computed.
+///
+/// Handed to [`super::BlobStore::put_staged`], which moves or copies it into
+/// the store. The temporary file is removed when the value is dropped.
+pub struct StagedBlob {
+    path: PathBuf,
+    key: String,
+    size: u64,
+}
+
+impl StagedBlob {
+    /// Spool `stream` into a new file under `dir`, hashing it on the way.
+    /// Fails with [`BlobError::TooLarge`] as soon as more than `max_bytes` arrive.
+    pub async fn from_stream<S, E>(
+        dir: &Path,
+        mut stream: S,
+        max_bytes: u64,
+    ) -> Result<Self, BlobError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        tokio::fs::create_dir_all(dir).await?;
+        let mut staged = StagedBlob {
+            path: dir.join(format!("{}.part", Uuid::new_v4())),
+            key: String::new(),
+            size: 0,
+        };
+        let mut file = tokio::fs::File::create(&staged.path).await?;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| BlobError::Io(std::io::Error::other(e)))?;
+            staged.size += chunk.len() as u64;
+            if staged.size > max_bytes {
+                return Err(BlobError::TooLarge);
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        staged.key = hex::encode(hasher.finalize());
+        Ok(staged)
+    }
+
+    /// Adopt an existing file, such as a completed resumable upload, truncating
+    /// it to `size` bytes and hashing what remains.
+    pub async fn from_file(path: PathBuf, size: u64) -> Result<Self, BlobError> {
+        let mut staged = StagedBlob {
+            path,
+            key: String::new(),
+            size,
+        };
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&staged.path)
+            .await?;
+        file.set_len(size).await?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        staged.key = hex::encode(hasher.finalize());
+        Ok(staged)
+    }
+
+    /// Content key (hex SHA-256) of the staged bytes.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagedBlob {
+    fn drop(&mut self) {
+        // Already gone if the store moved the file into place
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,119 @@
+//! Upload capabilities: invite tokens a recipient mints for specific senders.
+//!
+//! The mailbox owner runs the `/challenge` flow with scope `capability`; the
+//! decrypted JWT is the capability. It names the mailbox and limits the number
+//! of uploads, the size of each upload and its lifetime. Senders present it as
+//! `X-Capability` and stay anonymous; uses are counted in storage by `jti`,
+//! only once an upload is actually stored.
+
+use crate::config::{AppState, Config};
+use crate::error::StorageError;
+use crate::mailbox::storage_key;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::Validation;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Request header carrying a capability.
+pub const HEADER: &str = "X-Capability";
+
+/// Audience of capability JWTs; no authenticated endpoint accepts it.
+const AUDIENCE: &str = "/upload";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityClaims {
+    pub sub: String, // recipient mailbox
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: Uuid,
+    pub max_uploads: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>, // per upload
+}
+
+impl CapabilityClaims {
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.exp, 0).unwrap_or_default()
+    }
+}
+
+/// Sign a capability for uploads to `pubkey`. Uploads default to one and the
+/// lifetime to `CAPABILITY_MAX_TTL_SECONDS`, which also caps `expires_in`.
+pub fn issue(
+    config: &Config,
+    pubkey: &str,
+    max_uploads: Option<u32>,
+    max_bytes: Option<u64>,
+    expires_in: Option<i64>,
+) -> Result<String, (StatusCode, String)> {
+    let max_uploads = max_uploads.unwrap_or(1);
+    if max_uploads == 0 || max_bytes == Some(0) || expires_in.is_some_and(|ttl| ttl <= 0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Capability limits must be positive".to_string(),
+        ));
+    }
+    let now = Utc::now().timestamp();
+    let ttl = expires_in
+        .unwrap_or(config.capability_max_ttl_seconds)
+        .min(config.capability_max_ttl_seconds);
+    let claims = CapabilityClaims {
+        sub: pubkey.to_string(),
+        aud: AUDIENCE.to_string(),
+        iat: now,
+        exp: now + ttl,
+        jti: Uuid::new_v4(),
+        max_uploads,
+        max_bytes,
+    };
+    config.keyring.encode(&claims).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("JWT error: {}", e),
+        )
+    })
+}
+
+/// Check the signature, expiry and mailbox of a capability for `pubkey`.
+pub fn verify(
+    config: &Config,
+    pubkey: &str,
+    token: &str,
+) -> Result<CapabilityClaims, &'static str> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[AUDIENCE]);
+    // Use counters are swept at `exp`, so no grace period may outlive them
+    validation.leeway = 0;
+    let claims = config
+        .keyring
+        .decode::<CapabilityClaims>(token, &validation)
+        .map_err(|_| "Invalid or expired upload capability")?
+        .claims;
+    if claims.sub != pubkey {
+        return Err("Upload capability was issued for another mailbox");
+    }
+    Ok(claims)
+}
+
+/// Count one upload against `claims`, returning false once it is used up.
+pub async fn consume(state: &AppState, claims: &CapabilityClaims) -> Result<bool, StorageError> {
+    state
+        .storage
+        .consume_capability(
+            claims.jti,
+            &storage_key(&state.config, &claims.sub),
+            claims.max_uploads as i64,
+            claims.expires_at(),
+        )
+        .await
+}
+
+/// Give back the use counted by [`consume`] when the upload was not stored.
+pub async fn release(state: &AppState, claims: &CapabilityClaims) -> Result<(), StorageError> {
+    state.storage.release_capability(claims.jti).await
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,45 @@
+use super::*;
+use crate::config::test_config;
+
+#[test]
+fn test_issue_and_verify_capability() {
+    let config = test_config();
+    let token = issue(&config, "pk", Some(3), Some(1024), Some(60)).unwrap();
+    let claims = verify(&config, "pk", &token).unwrap();
+    assert_eq!(claims.max_uploads, 3);
+    assert_eq!(claims.max_bytes, Some(1024));
+    assert_eq!(claims.exp - claims.iat, 60);
+    assert!(verify(&config, "other", &token).is_err());
+    assert!(verify(&config, "pk", &format!("{}x", token)).is_err());
+}
+
+#[test]
+fn test_capability_limits() {
+    let mut config = test_config();
+    config.capability_max_ttl_seconds = 100;
+    let token = issue(&config, "pk", None, None, Some(1000)).unwrap();
+    let claims = verify(&config, "pk", &token).unwrap();
+    assert_eq!(claims.max_uploads, 1);
+    assert_eq!(claims.max_bytes, None);
+    assert_eq!(claims.exp - claims.iat, 100);
+    assert!(issue(&config, "pk", Some(0), None, None).is_err());
+    assert!(issue(&config, "pk", None, Some(0), None).is_err());
+    assert!(issue(&config, "pk", None, None, Some(-1)).is_err());
+}
+
+#[test]
+fn test_expired_capability_is_rejected_without_leeway() {
+    let config = test_config();
+    let now = Utc::now().timestamp();
+    let claims = CapabilityClaims {
+        sub: "pk".to_string(),
+        aud: AUDIENCE.to_string(),
+        iat: now - 60,
+        exp: now - 1,
+        jti: Uuid::new_v4(),
+        max_uploads: 1,
+        max_bytes: None,
+    };
+    let token = config.keyring.encode(&claims).unwrap();
+    assert!(verify(&config, "pk", &token).is_err());
+}
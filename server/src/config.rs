@@ -1,5 +1,11 @@
+use crate::blob::BlobStore;
+use crate::cursor::{self, CursorKey};
+use crate::keyring::Keyring;
+use crate::mailbox::MailboxHashKey;
+use crate::ratelimit::{RateLimit, RateLimitStore, deserialize_limit};
+use crate::replay::SeenTokenStore;
+use crate::storage::Storage;
 use serde::Deserialize;
-use sqlx::{Pool, Postgres};
 use std::sync::Arc;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -9,13 +15,99 @@ pub struct Config {
     #[serde(default = "default_port")]
     pub port: u16,
     pub database_url: String,
-    pub jwt_secret: String,
+    pub jwt_secret: Option<String>, // HS256 secret of the `default` key
+    pub jwt_keys_file: Option<String>, // JSON keyring for rotation, see `keyring`
+    #[serde(default = "default_jwt_algorithm")]
+    pub jwt_algorithm: String, // "HS256" or "EdDSA", unless JWT_KEYS_FILE is set
+    pub jwt_ed25519_key_file: Option<String>, // PKCS#8 PEM private key for EdDSA
+    #[serde(skip)]
+    pub keyring: Keyring, // built from the two settings above by `load_config`
     #[serde(default = "default_jwt_expiration")]
     pub jwt_expiration_seconds: i64,
+    pub mailbox_hash_secret: MailboxHashKey, // keys the mailbox ids stored in the database
+    #[serde(default = "default_single_use_scopes")]
+    pub single_use_scopes: Vec<String>, // challenge scopes whose tokens are rejected once used
+    #[serde(default = "default_replay_backend")]
+    pub replay_backend: String, // "memory" or "postgres" (shared between instances)
+    pub replay_database_url: Option<String>, // defaults to DATABASE_URL
+    #[serde(default = "default_replay_cache_capacity")]
+    pub replay_cache_capacity: usize, // seen tokens kept by the memory backend
     #[serde(default = "default_retrieve_page_size")]
     pub retrieve_page_size: u32, // New: default page size for /retrieve
+    #[serde(default = "default_cursor_ttl")]
+    pub cursor_ttl_seconds: i64, // lifetime of /retrieve pagination cursors
+    #[serde(default, deserialize_with = "cursor::deserialize_key")]
+    pub cursor_encryption_key: Option<CursorKey>, // encrypts cursors when set
     #[serde(default = "default_database_schema_version")]
     pub database_schema_version: u32,
+    pub telegram_bot_token: Option<String>,
+    #[serde(default = "default_telegram_api_base_url")]
+    pub telegram_api_base_url: String, // Bot API base URL, overridable for tests
+    #[serde(default = "default_blob_backend")]
+    pub blob_backend: String, // "local", "s3" or "memory"
+    #[serde(default = "default_blob_dir")]
+    pub blob_dir: String,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    #[serde(default = "default_s3_region")]
+    pub s3_region: String,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: u64,
+    pub upload_staging_dir: Option<String>, // defaults to a directory next to the blobs
+    #[serde(default)]
+    pub validate_age_uploads: bool, // reject bodies that are not age files for the mailbox
+    #[serde(default = "default_upload_session_ttl")]
+    pub upload_session_ttl_seconds: i64, // idle time before a resumable upload is discarded
+    #[serde(default = "default_upload_gc_interval")]
+    pub upload_gc_interval_seconds: u64,
+    pub max_item_age_seconds: Option<i64>, // global retention limit; unset keeps items forever
+    #[serde(default = "default_expiry_sweep_interval")]
+    pub expiry_sweep_interval_seconds: u64,
+    #[serde(default = "default_ack_grace_period")]
+    pub ack_grace_period_seconds: i64, // how long acknowledged items stay downloadable
+    pub mailbox_max_items: Option<i64>, // per-pubkey limits; unset is unlimited
+    pub mailbox_max_bytes: Option<i64>,
+    #[serde(default)]
+    pub pow_difficulty: u32, // leading zero bits required on uploads; 0 disables PoW
+    #[serde(default = "default_pow_max_difficulty")]
+    pub pow_max_difficulty: u32, // highest difficulty a mailbox owner may choose
+    #[serde(default = "default_pow_challenge_ttl")]
+    pub pow_challenge_ttl_seconds: i64,
+    #[serde(default = "default_capability_max_ttl")]
+    pub capability_max_ttl_seconds: i64, // longest lifetime of an upload capability
+    #[serde(default = "default_rate_limit_backend")]
+    pub rate_limit_backend: String, // "memory" or "postgres" (shared between instances)
+    pub rate_limit_database_url: Option<String>, // defaults to DATABASE_URL
+    #[serde(default)]
+    pub rate_limit_trust_proxy: bool, // key clients by X-Forwarded-For
+    // Per route group, `<requests>/<seconds>` per IP and per mailbox, or `off`
+    #[serde(
+        default = "default_rate_limit_upload",
+        deserialize_with = "deserialize_limit"
+    )]
+    pub rate_limit_upload: Option<RateLimit>,
+    #[serde(
+        default = "default_rate_limit_challenge",
+        deserialize_with = "deserialize_limit"
+    )]
+    pub rate_limit_challenge: Option<RateLimit>,
+    #[serde(
+        default = "default_rate_limit_retrieve",
+        deserialize_with = "deserialize_limit"
+    )]
+    pub rate_limit_retrieve: Option<RateLimit>,
+    #[serde(
+        default = "default_rate_limit_delete",
+        deserialize_with = "deserialize_limit"
+    )]
+    pub rate_limit_delete: Option<RateLimit>,
+    #[serde(
+        default = "default_rate_limit_notify",
+        deserialize_with = "deserialize_limit"
+    )]
+    pub rate_limit_notify: Option<RateLimit>,
 }
 
 fn default_host() -> String {
@@ -30,21 +122,164 @@ fn default_jwt_expiration() -> i64 {
     300 // 5 minutes default
 }
 
+fn default_jwt_algorithm() -> String {
+    "HS256".to_string()
+}
+
+fn default_single_use_scopes() -> Vec<String> {
+    vec!["notify".to_string()]
+}
+
+fn default_replay_backend() -> String {
+    "memory".to_string()
+}
+
+fn default_replay_cache_capacity() -> usize {
+    100_000
+}
+
 fn default_retrieve_page_size() -> u32 {
     50 // Default page size for pagination
 }
 
+fn default_cursor_ttl() -> i64 {
+    600 // 10 minutes
+}
+
 fn default_database_schema_version() -> u32 {
     0 // Default schema version
 }
 
+fn default_telegram_api_base_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_blob_backend() -> String {
+    "local".to_string()
+}
+
+fn default_blob_dir() -> String {
+    "blobs".to_string()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_max_upload_bytes() -> u64 {
+    1024 * 1024 * 1024 // 1 GiB
+}
+
+fn default_upload_session_ttl() -> i64 {
+    86400 // 24 hours
+}
+
+fn default_upload_gc_interval() -> u64 {
+    3600 // hourly
+}
+
+fn default_expiry_sweep_interval() -> u64 {
+    300 // 5 minutes
+}
+
+fn default_ack_grace_period() -> i64 {
+    3600 // 1 hour
+}
+
+fn default_pow_max_difficulty() -> u32 {
+    28
+}
+
+fn default_pow_challenge_ttl() -> i64 {
+    300 // 5 minutes
+}
+
+fn default_capability_max_ttl() -> i64 {
+    30 * 24 * 3600 // 30 days
+}
+
+fn default_rate_limit_backend() -> String {
+    "memory".to_string()
+}
+
+fn default_rate_limit_upload() -> Option<RateLimit> {
+    Some(RateLimit::new(60, 60))
+}
+
+fn default_rate_limit_challenge() -> Option<RateLimit> {
+    Some(RateLimit::new(10, 60))
+}
+
+fn default_rate_limit_retrieve() -> Option<RateLimit> {
+    Some(RateLimit::new(120, 60))
+}
+
+fn default_rate_limit_delete() -> Option<RateLimit> {
+    Some(RateLimit::new(60, 60))
+}
+
+fn default_rate_limit_notify() -> Option<RateLimit> {
+    Some(RateLimit::new(5, 60))
+}
+
 #[derive(Clone)]
 pub struct AppState {
-    pub db_pool: Arc<Pool<Postgres>>,
+    pub storage: Arc<dyn Storage>,
+    pub blobs: Arc<dyn BlobStore>,
     pub config: Arc<Config>,
+    pub http_client: reqwest::Client,
+    pub rate_limiter: Arc<dyn RateLimitStore>,
+    pub seen_tokens: Arc<dyn SeenTokenStore>,
 }
 
-pub fn load_config() -> Result<Config, envy::Error> {
+pub fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
     dotenvy::dotenv().ok(); // Load .env file if present
-    envy::from_env::<Config>()
+    let mut config = envy::from_env::<Config>().map_err(|e| match e {
+        // e.g. MAILBOX_HASH_SECRET, which deployments predating it lack
+        envy::Error::MissingValue(field) => {
+            format!("{} must be set, see .env.example", field.to_uppercase())
+        }
+        e => e.to_string(),
+    })?;
+    config.keyring = Keyring::from_config(&config)?;
+    Ok(config)
+}
+
+/// Minimal configuration for unit tests; every optional setting uses its default.
+#[cfg(test)]
+pub fn test_config() -> Config {
+    let mut config: Config = envy::from_iter([
+        ("DATABASE_URL".to_string(), "sqlite::memory:".to_string()),
+        (
+            "JWT_SECRET".to_string(),
+            "test_secret_1234567890".to_string(),
+        ),
+        ("JWT_EXPIRATION_SECONDS".to_string(), "60".to_string()),
+        (
+            "MAILBOX_HASH_SECRET".to_string(),
+            "test_mailbox_secret".to_string(),
+        ),
+        ("RETRIEVE_PAGE_SIZE".to_string(), "10".to_string()),
+        ("BLOB_BACKEND".to_string(), "memory".to_string()),
+        (
+            "TELEGRAM_API_BASE_URL".to_string(),
+            "http://127.0.0.1:9".to_string(),
+        ),
+    ])
+    .expect("Invalid test configuration");
+    config.keyring = Keyring::from_config(&config).expect("Invalid test keyring");
+    config
+}
+
+/// State with in-memory backends around `config`, for handler and task tests.
+#[cfg(test)]
+pub fn test_state(config: Config) -> AppState {
+    AppState {
+        storage: Arc::new(crate::storage::MemoryStorage::new()),
+        blobs: Arc::new(crate::blob::MemoryBlobStore::new()),
+        config: Arc::new(config),
+        http_client: reqwest::Client::new(),
+        rate_limiter: Arc::new(crate::ratelimit::MemoryRateLimitStore::new()),
+        seen_tokens: Arc::new(crate::replay::MemorySeenTokenStore::new(1000)),
+    }
 }
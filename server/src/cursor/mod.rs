@@ -0,0 +1,124 @@
+//! `/retrieve` pagination cursors: signed tokens naming the last item of a
+//! page, bound to the mailbox that requested it and valid for
+//! `CURSOR_TTL_SECONDS`. With `CURSOR_ENCRYPTION_KEY` set, cursors are also
+//! encrypted (XChaCha20-Poly1305) so clients cannot read the item timestamps
+//! and IDs inside them.
+
+use crate::config::Config;
+use crate::storage::PageKey;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::Validation;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+/// Audience of cursor tokens, so they are never accepted as auth tokens.
+const AUDIENCE: &str = "/retrieve-cursor";
+
+const NONCE_LEN: usize = 24;
+
+#[derive(Serialize, Deserialize)]
+struct CursorClaims {
+    sub: String, // mailbox the cursor was issued to
+    aud: String,
+    exp: i64,
+    created_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+/// 256-bit key from `CURSOR_ENCRYPTION_KEY` (64 hex characters).
+#[derive(Clone)]
+pub struct CursorKey([u8; 32]);
+
+impl fmt::Debug for CursorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CursorKey(..)")
+    }
+}
+
+/// Deserialize an optional [`CursorKey`] from config.
+pub fn deserialize_key<'de, D>(deserializer: D) -> Result<Option<CursorKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(value.trim(), &mut key)
+        .map_err(|_| serde::de::Error::custom("CURSOR_ENCRYPTION_KEY must be 64 hex characters"))?;
+    Ok(Some(CursorKey(key)))
+}
+
+/// Issue a cursor for `sub` continuing after `after`.
+pub fn encode(
+    config: &Config,
+    sub: &str,
+    after: PageKey,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let (created_at, id) = after;
+    let claims = CursorClaims {
+        sub: sub.to_string(),
+        aud: AUDIENCE.to_string(),
+        exp: (Utc::now() + Duration::seconds(config.cursor_ttl_seconds)).timestamp(),
+        created_at,
+        id,
+    };
+    let token = config.keyring.encode(&claims)?;
+    let Some(CursorKey(key)) = &config.cursor_encryption_key else {
+        return Ok(token);
+    };
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: token.as_bytes(),
+        aad: sub.as_bytes(),
+    };
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(&nonce, payload)
+        .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(URL_SAFE_NO_PAD.encode(sealed))
+}
+
+/// Check a cursor presented by `sub` and return the page position it names.
+/// Cursors issued to another mailbox, expired or tampered with are rejected.
+pub fn decode(config: &Config, sub: &str, cursor: &str) -> Result<PageKey, &'static str> {
+    let opened;
+    let token = match &config.cursor_encryption_key {
+        None => cursor,
+        Some(CursorKey(key)) => {
+            let sealed = URL_SAFE_NO_PAD
+                .decode(cursor)
+                .map_err(|_| "Invalid cursor")?;
+            if sealed.len() < NONCE_LEN {
+                return Err("Invalid cursor");
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let payload = Payload {
+                msg: ciphertext,
+                aad: sub.as_bytes(),
+            };
+            let plaintext = XChaCha20Poly1305::new(key.into())
+                .decrypt(XNonce::from_slice(nonce), payload)
+                .map_err(|_| "Invalid cursor")?;
+            opened = String::from_utf8(plaintext).map_err(|_| "Invalid cursor")?;
+            &opened
+        }
+    };
+    let mut validation = Validation::default();
+    validation.set_audience(&[AUDIENCE]);
+    let claims = config
+        .keyring
+        .decode::<CursorClaims>(token, &validation)
+        .map_err(|_| "Invalid cursor")?
+        .claims;
+    if claims.sub != sub {
+        return Err("Cursor was issued for another mailbox");
+    }
+    Ok((claims.created_at, claims.id))
+}
+
+#[cfg(test)]
+mod tests;
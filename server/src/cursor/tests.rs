@@ -0,0 +1,59 @@
+use super::*;
+use crate::config::test_config;
+
+fn page_key() -> PageKey {
+    (Utc::now(), Uuid::new_v4())
+}
+
+#[test]
+fn test_cursor_round_trip() {
+    let config = test_config();
+    let after = page_key();
+    let cursor = encode(&config, "pk", after).unwrap();
+    assert_eq!(decode(&config, "pk", &cursor), Ok(after));
+    assert!(decode(&config, "other", &cursor).is_err());
+    assert!(decode(&config, "pk", "garbage").is_err());
+}
+
+#[test]
+fn test_cursor_ttl() {
+    let mut config = test_config();
+    // Past the default 60s leeway of the JWT validation
+    config.cursor_ttl_seconds = -120;
+    let cursor = encode(&config, "pk", page_key()).unwrap();
+    assert!(decode(&config, "pk", &cursor).is_err());
+}
+
+#[test]
+fn test_encrypted_cursor() {
+    let mut config = test_config();
+    config.cursor_encryption_key = Some(CursorKey([7; 32]));
+    let after = page_key();
+    let cursor = encode(&config, "pk", after).unwrap();
+    // Not a readable JWT, and decodable only by the same mailbox and key
+    assert!(jsonwebtoken::decode_header(&cursor).is_err());
+    assert_eq!(decode(&config, "pk", &cursor), Ok(after));
+    assert!(decode(&config, "other", &cursor).is_err());
+    let plain = encode(&test_config(), "pk", after).unwrap();
+    assert!(decode(&config, "pk", &plain).is_err());
+    config.cursor_encryption_key = Some(CursorKey([8; 32]));
+    assert!(decode(&config, "pk", &cursor).is_err());
+}
+
+#[test]
+fn test_cursor_key_config() {
+    let config = |key: &str| {
+        envy::from_iter::<_, Config>([
+            ("DATABASE_URL".to_string(), "memory:".to_string()),
+            ("MAILBOX_HASH_SECRET".to_string(), "secret".to_string()),
+            ("CURSOR_ENCRYPTION_KEY".to_string(), key.to_string()),
+        ])
+    };
+    assert!(
+        config(&"ab".repeat(32))
+            .unwrap()
+            .cursor_encryption_key
+            .is_some()
+    );
+    assert!(config("abcd").is_err());
+}
@@ -1,47 +1,802 @@
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use crate::agefile::AgeFormat;
+use crate::capability::CapabilityClaims;
+use crate::error::StorageError;
+use crate::storage::{DeliveryFilter, PageKey, Quota, Storage, Usage};
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use sqlx::{Executor, FromRow, PgPool};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Executor, FromRow, PgExecutor, PgPool};
 use uuid::Uuid;
 
-#[derive(Debug, FromRow)]
+/// Columns loaded into [`DbItem`]. Queries never use `SELECT *`, so legacy
+/// inline ciphertexts are not read along with the metadata.
+const ITEM_COLUMNS: &str = "id, pubkey, blob_key, size, read_once, created_at";
+
+/// Columns loaded into [`ItemMeta`].
+const ITEM_META_COLUMNS: &str =
+    "id, created_at, size, labels, expires_at, read_once, delivered_at, format";
+
+/// `WHERE` clause fragment (with a leading ` AND`) implementing a [`DeliveryFilter`].
+fn delivery_condition(filter: DeliveryFilter) -> &'static str {
+    match filter {
+        DeliveryFilter::All => "",
+        DeliveryFilter::Delivered => " AND delivered_at IS NOT NULL",
+        DeliveryFilter::Undelivered => " AND delivered_at IS NULL",
+    }
+}
+
+/// Metadata needed to store a new item; the ciphertext is already in the blob store.
+#[derive(Debug, Clone)]
+pub struct NewItem {
+    pub pubkey: String, // mailbox storage key, see `mailbox::storage_key`
+    pub blob_key: String,
+    pub size: i64,
+    pub labels: Option<Vec<String>>, // sender-supplied, stored in plaintext
+    pub expires_at: Option<DateTime<Utc>>,
+    pub read_once: bool,           // deleted by the first download
+    pub format: Option<AgeFormat>, // known when the upload was validated
+}
+
+impl NewItem {
+    pub fn new(pubkey: &str, blob_key: &str, size: i64) -> Self {
+        NewItem {
+            pubkey: pubkey.to_string(),
+            blob_key: blob_key.to_string(),
+            size,
+            labels: None,
+            expires_at: None,
+            read_once: false,
+            format: None,
+        }
+    }
+}
+
+/// Lightweight projection of an item used for listing mailboxes.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct ItemMeta {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Json<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub read_once: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<DateTime<Utc>>, // set when the recipient acknowledged the item
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>, // "binary" or "armored", see `agefile`
+}
+
+#[derive(Debug, Clone, FromRow)]
 pub struct DbItem {
     pub id: Uuid,
-    pub pubkey: String, // base64 encoded X25519 pubkey
-    pub ciphertext: Vec<u8>,
+    pub pubkey: String,   // hex HMAC of the mailbox, see `mailbox::storage_key`
+    pub blob_key: String, // content key of the ciphertext in the blob store
+    pub size: i64,        // ciphertext length in bytes
+    pub read_once: bool,
     pub created_at: DateTime<Utc>,
 }
 
 impl DbItem {
-    pub async fn insert(pool: &PgPool, pubkey: &str, ciphertext: &[u8]) -> sqlx::Result<DbItem> {
-        let rec = sqlx::query_as::<_, DbItem>(
-            "INSERT INTO items (id, pubkey, ciphertext, created_at) VALUES ($1, $2, $3, $4) RETURNING *"
-        )
+    pub async fn insert<'e>(executor: impl PgExecutor<'e>, item: &NewItem) -> sqlx::Result<DbItem> {
+        let rec = sqlx::query_as::<_, DbItem>(&format!(
+            "INSERT INTO items (id, pubkey, blob_key, size, labels, created_at, expires_at, read_once, format) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING {}",
+            ITEM_COLUMNS
+        ))
         .bind(Uuid::new_v4())
-        .bind(pubkey)
-        .bind(ciphertext)
+        .bind(&item.pubkey)
+        .bind(&item.blob_key)
+        .bind(item.size)
+        .bind(item.labels.as_ref().map(Json))
         .bind(Utc::now())
-        .fetch_one(pool)
+        .bind(item.expires_at)
+        .bind(item.read_once)
+        .bind(item.format.map(AgeFormat::as_str))
+        .fetch_one(executor)
         .await?;
         Ok(rec)
     }
 
-    pub async fn get_items_for_pubkey(pool: &PgPool, pubkey: &str) -> sqlx::Result<Vec<DbItem>> {
-        sqlx::query_as::<_, DbItem>(
-            "SELECT * FROM items WHERE pubkey = $1 ORDER BY created_at DESC",
+    /// Insert an item unless its mailbox would exceed `quota`, returning `None`
+    /// in that case. A per-pubkey advisory lock serializes concurrent inserts
+    /// for the same mailbox until the transaction ends.
+    pub async fn insert_within_quota(
+        pool: &PgPool,
+        item: &NewItem,
+        quota: Quota,
+    ) -> sqlx::Result<Option<DbItem>> {
+        if quota.is_unlimited() {
+            return Self::insert(pool, item).await.map(Some);
+        }
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(&item.pubkey)
+            .execute(&mut *tx)
+            .await?;
+        let usage = Self::usage_for_pubkey(&mut *tx, &item.pubkey).await?;
+        if !quota.allows(usage, item.size) {
+            return Ok(None);
+        }
+        let rec = Self::insert(&mut *tx, item).await?;
+        tx.commit().await?;
+        Ok(Some(rec))
+    }
+
+    /// Count and total size of the unexpired items for a pubkey.
+    pub async fn usage_for_pubkey<'e>(
+        executor: impl PgExecutor<'e>,
+        pubkey: &str,
+    ) -> sqlx::Result<Usage> {
+        let (items, bytes): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0)::BIGINT FROM items \
+             WHERE pubkey = $1 AND (expires_at IS NULL OR expires_at > $2)",
+        )
+        .bind(pubkey)
+        .bind(Utc::now())
+        .fetch_one(executor)
+        .await?;
+        Ok(Usage { items, bytes })
+    }
+
+    /// One page of unexpired items for a pubkey, ordered by created_at DESC, id DESC.
+    pub async fn get_page_for_pubkey(
+        pool: &PgPool,
+        pubkey: &str,
+        filter: DeliveryFilter,
+        after: Option<PageKey>,
+        limit: u32,
+    ) -> sqlx::Result<Vec<ItemMeta>> {
+        if let Some((created_at, id)) = after {
+            sqlx::query_as::<_, ItemMeta>(&format!(
+                "SELECT {} FROM items WHERE pubkey = $1 AND (expires_at IS NULL OR expires_at > $2){} AND (created_at < $3 OR (created_at = $3 AND id < $4)) ORDER BY created_at DESC, id DESC LIMIT $5",
+                ITEM_META_COLUMNS,
+                delivery_condition(filter)
+            ))
+            .bind(pubkey)
+            .bind(Utc::now())
+            .bind(created_at)
+            .bind(id)
+            .bind(limit as i64)
+            .fetch_all(pool)
+            .await
+        } else {
+            sqlx::query_as::<_, ItemMeta>(&format!(
+                "SELECT {} FROM items WHERE pubkey = $1 AND (expires_at IS NULL OR expires_at > $2){} ORDER BY created_at DESC, id DESC LIMIT $3",
+                ITEM_META_COLUMNS,
+                delivery_condition(filter)
+            ))
+            .bind(pubkey)
+            .bind(Utc::now())
+            .bind(limit as i64)
+            .fetch_all(pool)
+            .await
+        }
+    }
+
+    /// Look up an item; expired items are treated as missing.
+    pub async fn get_item_by_id(pool: &PgPool, id: Uuid) -> sqlx::Result<Option<DbItem>> {
+        sqlx::query_as::<_, DbItem>(&format!(
+            "SELECT {} FROM items WHERE id = $1 AND (expires_at IS NULL OR expires_at > $2)",
+            ITEM_COLUMNS
+        ))
+        .bind(id)
+        .bind(Utc::now())
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Atomically delete and return an unexpired read-once item owned by `pubkey`.
+    pub async fn take_read_once(
+        pool: &PgPool,
+        id: Uuid,
+        pubkey: &str,
+    ) -> sqlx::Result<Option<DbItem>> {
+        sqlx::query_as::<_, DbItem>(&format!(
+            "DELETE FROM items WHERE id = $1 AND pubkey = $2 AND read_once \
+             AND (expires_at IS NULL OR expires_at > $3) RETURNING {}",
+            ITEM_COLUMNS
+        ))
+        .bind(id)
+        .bind(pubkey)
+        .bind(Utc::now())
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Mark an unexpired item as delivered and schedule its purge; see
+    /// [`Storage::acknowledge_item`].
+    pub async fn acknowledge(
+        pool: &PgPool,
+        id: Uuid,
+        pubkey: &str,
+        now: DateTime<Utc>,
+        purge_at: DateTime<Utc>,
+    ) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE items SET delivered_at = COALESCE(delivered_at, $3), expires_at = LEAST(expires_at, $4) \
+             WHERE id = $1 AND pubkey = $2 AND (expires_at IS NULL OR expires_at > $3)",
         )
+        .bind(id)
         .bind(pubkey)
+        .bind(now)
+        .bind(purge_at)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Up to `limit` items that expired by `now`, or that were created before
+    /// `created_before` (the global max age) regardless of their own expiry.
+    pub async fn get_expired(
+        pool: &PgPool,
+        now: DateTime<Utc>,
+        created_before: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> sqlx::Result<Vec<DbItem>> {
+        sqlx::query_as::<_, DbItem>(&format!(
+            "SELECT {} FROM items WHERE expires_at <= $1 OR created_at < $2 LIMIT $3",
+            ITEM_COLUMNS
+        ))
+        .bind(now)
+        .bind(created_before)
+        .bind(limit as i64)
         .fetch_all(pool)
         .await
     }
 
-    pub async fn get_item_by_id(pool: &PgPool, id: Uuid) -> sqlx::Result<Option<DbItem>> {
-        sqlx::query_as::<_, DbItem>("SELECT * FROM items WHERE id = $1")
+    /// Legacy rows whose ciphertext predates the blob store.
+    pub async fn get_inline_ciphertexts(
+        pool: &PgPool,
+        limit: u32,
+    ) -> sqlx::Result<Vec<(Uuid, Vec<u8>)>> {
+        sqlx::query_as(
+            "SELECT id, ciphertext FROM items WHERE blob_key IS NULL AND ciphertext IS NOT NULL LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn set_blob_ref(
+        pool: &PgPool,
+        id: Uuid,
+        blob_key: &str,
+        size: i64,
+    ) -> sqlx::Result<()> {
+        sqlx::query("UPDATE items SET blob_key = $2, size = $3, ciphertext = NULL WHERE id = $1")
+            .bind(id)
+            .bind(blob_key)
+            .bind(size)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn count_by_blob_key(pool: &PgPool, blob_key: &str) -> sqlx::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM items WHERE blob_key = $1")
+            .bind(blob_key)
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
+    pub async fn delete_by_id(pool: &PgPool, id: Uuid) -> sqlx::Result<bool> {
+        let result = sqlx::query("DELETE FROM items WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Options for a new resumable upload, applied to the item on finalize.
+#[derive(Debug, Clone)]
+pub struct NewUploadSession {
+    pub pubkey: String,
+    pub labels: Option<Vec<String>>,
+    pub expires_in: Option<i64>, // requested item TTL in seconds
+    pub read_once: bool,
+    pub max_size: Option<i64>, // byte limit of the upload capability used, if any
+    pub capability: Option<CapabilityClaims>, // counted as a use on finalize
+}
+
+impl NewUploadSession {
+    pub fn new(pubkey: &str) -> Self {
+        NewUploadSession {
+            pubkey: pubkey.to_string(),
+            labels: None,
+            expires_in: None,
+            read_once: false,
+            max_size: None,
+            capability: None,
+        }
+    }
+}
+
+/// A resumable upload in progress; its bytes are spooled to the staging dir
+/// until the session is finalized into an item.
+#[derive(Debug, Clone, FromRow)]
+pub struct DbUploadSession {
+    pub id: Uuid,
+    pub pubkey: String,
+    pub labels: Option<Json<Vec<String>>>,
+    pub expires_in: Option<i64>, // requested item TTL in seconds, applied on finalize
+    pub read_once: bool,
+    pub received: i64, // bytes acknowledged so far
+    pub max_size: Option<i64>,
+    pub capability: Option<Json<CapabilityClaims>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DbUploadSession {
+    pub async fn create(
+        pool: &PgPool,
+        session: &NewUploadSession,
+    ) -> sqlx::Result<DbUploadSession> {
+        let now = Utc::now();
+        sqlx::query_as::<_, DbUploadSession>(
+            "INSERT INTO upload_sessions (id, pubkey, labels, expires_in, read_once, max_size, capability, received, created_at, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, 0, $8, $8) RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(&session.pubkey)
+        .bind(session.labels.as_ref().map(Json))
+        .bind(session.expires_in)
+        .bind(session.read_once)
+        .bind(session.max_size)
+        .bind(session.capability.as_ref().map(Json))
+        .bind(now)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn get_by_id(pool: &PgPool, id: Uuid) -> sqlx::Result<Option<DbUploadSession>> {
+        sqlx::query_as::<_, DbUploadSession>("SELECT * FROM upload_sessions WHERE id = $1")
             .bind(id)
             .fetch_optional(pool)
             .await
     }
+
+    pub async fn advance(pool: &PgPool, id: Uuid, from: i64, to: i64) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE upload_sessions SET received = $3, updated_at = $4 WHERE id = $1 AND received = $2",
+        )
+        .bind(id)
+        .bind(from)
+        .bind(to)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_by_id(pool: &PgPool, id: Uuid) -> sqlx::Result<bool> {
+        let result = sqlx::query("DELETE FROM upload_sessions WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_stale(pool: &PgPool, before: DateTime<Utc>) -> sqlx::Result<Vec<Uuid>> {
+        let rows: Vec<(Uuid,)> =
+            sqlx::query_as("SELECT id FROM upload_sessions WHERE updated_at < $1")
+                .bind(before)
+                .fetch_all(pool)
+                .await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+}
+
+/// Policy a recipient sets for their own mailbox.
+#[derive(Debug, Clone, Default, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+pub struct MailboxSettings {
+    pub pow_difficulty: Option<i32>, // raises POW_DIFFICULTY for uploads to this mailbox
+    #[serde(default)]
+    pub capability_required: bool, // only accept uploads carrying an upload capability
 }
 
-/// Run database migrations: create schema_version and items tables if needed.
+impl MailboxSettings {
+    pub async fn get_for_pubkey(pool: &PgPool, pubkey: &str) -> sqlx::Result<MailboxSettings> {
+        let settings = sqlx::query_as::<_, MailboxSettings>(
+            "SELECT pow_difficulty, capability_required FROM mailbox_settings WHERE pubkey = $1",
+        )
+        .bind(pubkey)
+        .fetch_optional(pool)
+        .await?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    /// Replace the settings of a mailbox.
+    pub async fn upsert(
+        pool: &PgPool,
+        pubkey: &str,
+        settings: &MailboxSettings,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO mailbox_settings (pubkey, pow_difficulty, capability_required, updated_at) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (pubkey) DO UPDATE SET pow_difficulty = EXCLUDED.pow_difficulty, \
+             capability_required = EXCLUDED.capability_required, updated_at = EXCLUDED.updated_at",
+        )
+        .bind(pubkey)
+        .bind(settings.pow_difficulty)
+        .bind(settings.capability_required)
+        .bind(Utc::now())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Use counter of an upload capability, kept until the capability expires.
+pub struct DbCapabilityUses;
+
+impl DbCapabilityUses {
+    /// Count one use of capability `id` unless it already had `max_uses`.
+    pub async fn consume(
+        pool: &PgPool,
+        id: Uuid,
+        pubkey: &str,
+        max_uses: i64,
+        expires_at: DateTime<Utc>,
+    ) -> sqlx::Result<bool> {
+        let row: Option<(i64,)> = sqlx::query_as(
+            "INSERT INTO capability_uses (id, pubkey, uses, expires_at) VALUES ($1, $2, 1, $4) \
+             ON CONFLICT (id) DO UPDATE SET uses = capability_uses.uses + 1 \
+             WHERE capability_uses.uses < $3 RETURNING uses",
+        )
+        .bind(id)
+        .bind(pubkey)
+        .bind(max_uses)
+        .bind(expires_at)
+        .fetch_optional(pool)
+        .await?;
+        Ok(row.is_some_and(|(uses,)| uses <= max_uses))
+    }
+
+    /// Undo one [`DbCapabilityUses::consume`] of capability `id`.
+    pub async fn release(pool: &PgPool, id: Uuid) -> sqlx::Result<()> {
+        sqlx::query("UPDATE capability_uses SET uses = uses - 1 WHERE id = $1 AND uses > 0")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_expired(pool: &PgPool, now: DateTime<Utc>) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM capability_uses WHERE expires_at <= $1")
+            .bind(now)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct DbNotification {
+    pub pubkey: String,
+    pub telegram: String, // Telegram chat ID or @handle
+    pub created_at: DateTime<Utc>,
+}
+
+impl DbNotification {
+    /// Register (or replace) the Telegram target for a pubkey.
+    pub async fn upsert(
+        pool: &PgPool,
+        pubkey: &str,
+        telegram: &str,
+    ) -> sqlx::Result<DbNotification> {
+        sqlx::query_as::<_, DbNotification>(
+            "INSERT INTO notifications (pubkey, telegram, created_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (pubkey) DO UPDATE SET telegram = EXCLUDED.telegram, created_at = EXCLUDED.created_at \
+             RETURNING *",
+        )
+        .bind(pubkey)
+        .bind(telegram)
+        .bind(Utc::now())
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn get_for_pubkey(
+        pool: &PgPool,
+        pubkey: &str,
+    ) -> sqlx::Result<Option<DbNotification>> {
+        sqlx::query_as::<_, DbNotification>("SELECT * FROM notifications WHERE pubkey = $1")
+            .bind(pubkey)
+            .fetch_optional(pool)
+            .await
+    }
+}
+
+/// PostgreSQL implementation of [`Storage`].
+pub struct PgStorage {
+    pool: PgPool,
+}
+
+impl PgStorage {
+    pub fn new(pool: PgPool) -> Self {
+        PgStorage { pool }
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn insert_item(&self, item: &NewItem, quota: Quota) -> Result<DbItem, StorageError> {
+        DbItem::insert_within_quota(&self.pool, item, quota)
+            .await?
+            .ok_or(StorageError::QuotaExceeded)
+    }
+
+    async fn mailbox_usage(&self, pubkey: &str) -> Result<Usage, StorageError> {
+        Ok(DbItem::usage_for_pubkey(&self.pool, pubkey).await?)
+    }
+
+    async fn list_items(
+        &self,
+        pubkey: &str,
+        filter: DeliveryFilter,
+        after: Option<PageKey>,
+        limit: u32,
+    ) -> Result<Vec<ItemMeta>, StorageError> {
+        Ok(DbItem::get_page_for_pubkey(&self.pool, pubkey, filter, after, limit).await?)
+    }
+
+    async fn get_item(&self, id: Uuid) -> Result<Option<DbItem>, StorageError> {
+        Ok(DbItem::get_item_by_id(&self.pool, id).await?)
+    }
+
+    async fn delete_item(&self, id: Uuid) -> Result<bool, StorageError> {
+        Ok(DbItem::delete_by_id(&self.pool, id).await?)
+    }
+
+    async fn count_blob_refs(&self, blob_key: &str) -> Result<i64, StorageError> {
+        Ok(DbItem::count_by_blob_key(&self.pool, blob_key).await?)
+    }
+
+    async fn take_read_once_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+    ) -> Result<Option<DbItem>, StorageError> {
+        Ok(DbItem::take_read_once(&self.pool, id, pubkey).await?)
+    }
+
+    async fn acknowledge_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        now: DateTime<Utc>,
+        purge_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        Ok(DbItem::acknowledge(&self.pool, id, pubkey, now, purge_at).await?)
+    }
+
+    async fn expired_items(
+        &self,
+        now: DateTime<Utc>,
+        created_before: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<DbItem>, StorageError> {
+        Ok(DbItem::get_expired(&self.pool, now, created_before, limit).await?)
+    }
+
+    async fn upsert_notification(
+        &self,
+        pubkey: &str,
+        telegram: &str,
+    ) -> Result<DbNotification, StorageError> {
+        Ok(DbNotification::upsert(&self.pool, pubkey, telegram).await?)
+    }
+
+    async fn get_notification(&self, pubkey: &str) -> Result<Option<DbNotification>, StorageError> {
+        Ok(DbNotification::get_for_pubkey(&self.pool, pubkey).await?)
+    }
+
+    async fn get_mailbox_settings(&self, pubkey: &str) -> Result<MailboxSettings, StorageError> {
+        Ok(MailboxSettings::get_for_pubkey(&self.pool, pubkey).await?)
+    }
+
+    async fn set_mailbox_settings(
+        &self,
+        pubkey: &str,
+        settings: &MailboxSettings,
+    ) -> Result<(), StorageError> {
+        Ok(MailboxSettings::upsert(&self.pool, pubkey, settings).await?)
+    }
+
+    async fn consume_capability(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        max_uses: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        Ok(DbCapabilityUses::consume(&self.pool, id, pubkey, max_uses, expires_at).await?)
+    }
+
+    async fn release_capability(&self, id: Uuid) -> Result<(), StorageError> {
+        Ok(DbCapabilityUses::release(&self.pool, id).await?)
+    }
+
+    async fn delete_expired_capabilities(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        Ok(DbCapabilityUses::delete_expired(&self.pool, now).await?)
+    }
+
+    async fn unhashed_pubkeys(&self) -> Result<Vec<String>, StorageError> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT pubkey FROM unhashed_mailboxes")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|(pubkey,)| pubkey).collect())
+    }
+
+    async fn rename_mailbox(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+        for statement in [
+            "UPDATE items SET pubkey = $2 WHERE pubkey = $1",
+            "UPDATE upload_sessions SET pubkey = $2 WHERE pubkey = $1",
+            "UPDATE capability_uses SET pubkey = $2 WHERE pubkey = $1",
+            "INSERT INTO notifications (pubkey, telegram, created_at) \
+             SELECT $2, telegram, created_at FROM notifications WHERE pubkey = $1 \
+             ON CONFLICT (pubkey) DO NOTHING",
+            "DELETE FROM notifications WHERE pubkey = $1",
+            "INSERT INTO mailbox_settings (pubkey, pow_difficulty, capability_required, updated_at) \
+             SELECT $2, pow_difficulty, capability_required, updated_at FROM mailbox_settings \
+             WHERE pubkey = $1 ON CONFLICT (pubkey) DO NOTHING",
+            "DELETE FROM mailbox_settings WHERE pubkey = $1",
+        ] {
+            sqlx::query(statement)
+                .bind(from)
+                .bind(to)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("DELETE FROM unhashed_mailboxes WHERE pubkey = $1")
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn skip_unhashed_pubkey(&self, pubkey: &str) -> Result<(), StorageError> {
+        sqlx::query("DELETE FROM unhashed_mailboxes WHERE pubkey = $1")
+            .bind(pubkey)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn inline_ciphertexts(&self, limit: u32) -> Result<Vec<(Uuid, Vec<u8>)>, StorageError> {
+        Ok(DbItem::get_inline_ciphertexts(&self.pool, limit).await?)
+    }
+
+    async fn set_blob_ref(&self, id: Uuid, blob_key: &str, size: i64) -> Result<(), StorageError> {
+        Ok(DbItem::set_blob_ref(&self.pool, id, blob_key, size).await?)
+    }
+
+    async fn create_upload_session(
+        &self,
+        session: &NewUploadSession,
+    ) -> Result<DbUploadSession, StorageError> {
+        Ok(DbUploadSession::create(&self.pool, session).await?)
+    }
+
+    async fn get_upload_session(&self, id: Uuid) -> Result<Option<DbUploadSession>, StorageError> {
+        Ok(DbUploadSession::get_by_id(&self.pool, id).await?)
+    }
+
+    async fn advance_upload_session(
+        &self,
+        id: Uuid,
+        from: i64,
+        to: i64,
+    ) -> Result<bool, StorageError> {
+        Ok(DbUploadSession::advance(&self.pool, id, from, to).await?)
+    }
+
+    async fn delete_upload_session(&self, id: Uuid) -> Result<bool, StorageError> {
+        Ok(DbUploadSession::delete_by_id(&self.pool, id).await?)
+    }
+
+    async fn stale_upload_sessions(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>, StorageError> {
+        Ok(DbUploadSession::get_stale(&self.pool, before).await?)
+    }
+}
+
+/// Schema changes applied on top of the base tables, in order.
+/// `schema_version` records how many of them have been applied.
+const MIGRATIONS: &[&str] = &[
+    // 1: ciphertext bodies move to the blob store; `ciphertext` is kept only
+    // for legacy rows until `blob::migrate_inline_ciphertexts` has run
+    r#"
+    ALTER TABLE items ALTER COLUMN ciphertext DROP NOT NULL;
+    ALTER TABLE items ADD COLUMN blob_key TEXT;
+    ALTER TABLE items ADD COLUMN size BIGINT NOT NULL DEFAULT 0;
+    "#,
+    // 2: optional sender labels and an index matching the /retrieve keyset pagination
+    r#"
+    ALTER TABLE items ADD COLUMN labels JSONB;
+    CREATE INDEX IF NOT EXISTS items_pubkey_created_at_id_idx
+        ON items (pubkey, created_at DESC, id DESC);
+    "#,
+    // 3: resumable upload sessions
+    r#"
+    CREATE TABLE upload_sessions (
+        id UUID PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        labels JSONB,
+        received BIGINT NOT NULL DEFAULT 0,
+        created_at TIMESTAMPTZ NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL
+    );
+    "#,
+    // 4: per-item expiry (sender TTL capped by the global max age)
+    r#"
+    ALTER TABLE items ADD COLUMN expires_at TIMESTAMPTZ;
+    ALTER TABLE upload_sessions ADD COLUMN expires_in BIGINT;
+    CREATE INDEX IF NOT EXISTS items_expires_at_idx ON items (expires_at);
+    "#,
+    // 5: burn-after-reading items
+    r#"
+    ALTER TABLE items ADD COLUMN read_once BOOLEAN NOT NULL DEFAULT FALSE;
+    ALTER TABLE upload_sessions ADD COLUMN read_once BOOLEAN NOT NULL DEFAULT FALSE;
+    "#,
+    // 6: recipient acknowledgements; acknowledged items expire after a grace period
+    r#"
+    ALTER TABLE items ADD COLUMN delivered_at TIMESTAMPTZ;
+    "#,
+    // 7: per-mailbox settings chosen by the recipient
+    r#"
+    CREATE TABLE mailbox_settings (
+        pubkey TEXT PRIMARY KEY,
+        pow_difficulty INTEGER,
+        updated_at TIMESTAMPTZ NOT NULL
+    );
+    "#,
+    // 8: recipient-issued upload capabilities
+    r#"
+    ALTER TABLE mailbox_settings ADD COLUMN capability_required BOOLEAN NOT NULL DEFAULT FALSE;
+    ALTER TABLE upload_sessions ADD COLUMN max_size BIGINT;
+    CREATE TABLE capability_uses (
+        id UUID PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        uses BIGINT NOT NULL,
+        expires_at TIMESTAMPTZ NOT NULL
+    );
+    "#,
+    // 9: encoding of validated age uploads
+    r#"
+    ALTER TABLE items ADD COLUMN format TEXT;
+    "#,
+    // 10: raw pubkeys to rewrite under `mailbox::storage_key`, recorded once
+    // so that later startups do not scan every table
+    r#"
+    CREATE TABLE unhashed_mailboxes (pubkey TEXT PRIMARY KEY);
+    INSERT INTO unhashed_mailboxes (pubkey)
+        SELECT pubkey FROM items
+        UNION SELECT pubkey FROM upload_sessions
+        UNION SELECT pubkey FROM notifications
+        UNION SELECT pubkey FROM mailbox_settings
+        UNION SELECT pubkey FROM capability_uses;
+    "#,
+    // 11: capability of a resumable upload, counted when it is finalized
+    r#"
+    ALTER TABLE upload_sessions ADD COLUMN capability JSONB;
+    "#,
+];
+
+/// Run database migrations: create schema_version, items and notifications tables
+/// if needed, then apply any pending [`MIGRATIONS`].
 pub async fn db_migrate(pool: &PgPool) -> Result<(), sqlx::Error> {
     // Create schema_version table if it doesn't exist
     pool.execute(
@@ -73,6 +828,30 @@ pub async fn db_migrate(pool: &PgPool) -> Result<(), sqlx::Error> {
     "#,
     )
     .await?;
+    // Create notifications table if it doesn't exist (one Telegram target per pubkey)
+    pool.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS notifications (
+            pubkey TEXT PRIMARY KEY,
+            telegram TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL
+        )
+    "#,
+    )
+    .await?;
+    // Apply pending schema migrations, each in its own transaction
+    let (version,): (i32,) = sqlx::query_as("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let mut tx = pool.begin().await?;
+        tx.execute(*migration).await?;
+        sqlx::query("UPDATE schema_version SET version = $1")
+            .bind(index as i32 + 1)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
     Ok(())
 }
 
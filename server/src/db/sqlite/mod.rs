@@ -0,0 +1,689 @@
+use crate::agefile::AgeFormat;
+use crate::capability::CapabilityClaims;
+use crate::db::{
+    DbItem, DbNotification, DbUploadSession, ITEM_COLUMNS, ITEM_META_COLUMNS, ItemMeta,
+    MailboxSettings, NewItem, NewUploadSession, delivery_condition,
+};
+use crate::error::StorageError;
+use crate::storage::{DeliveryFilter, PageKey, Quota, Storage, Usage};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::types::Json;
+use sqlx::{Executor, FromRow};
+use std::str::FromStr;
+use uuid::Uuid;
+
+// SQLite has no native timestamp type and sqlx stores `DateTime` as variable
+// precision RFC 3339 text, which does not sort correctly. Timestamps are kept
+// as microseconds since the epoch instead, matching PostgreSQL's precision.
+#[derive(FromRow)]
+struct SqliteItemRow {
+    id: Uuid,
+    pubkey: String, // mailbox storage key (hex HMAC), as in `DbItem`
+    blob_key: String,
+    size: i64,
+    read_once: bool,
+    created_at: i64,
+}
+
+impl From<SqliteItemRow> for DbItem {
+    fn from(row: SqliteItemRow) -> Self {
+        DbItem {
+            id: row.id,
+            pubkey: row.pubkey,
+            blob_key: row.blob_key,
+            size: row.size,
+            read_once: row.read_once,
+            created_at: from_micros(row.created_at),
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct SqliteItemMetaRow {
+    id: Uuid,
+    created_at: i64,
+    size: i64,
+    labels: Option<Json<Vec<String>>>,
+    expires_at: Option<i64>,
+    read_once: bool,
+    delivered_at: Option<i64>,
+    format: Option<String>,
+}
+
+impl From<SqliteItemMetaRow> for ItemMeta {
+    fn from(row: SqliteItemMetaRow) -> Self {
+        ItemMeta {
+            id: row.id,
+            created_at: from_micros(row.created_at),
+            size: row.size,
+            labels: row.labels,
+            expires_at: row.expires_at.map(from_micros),
+            read_once: row.read_once,
+            delivered_at: row.delivered_at.map(from_micros),
+            format: row.format,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct SqliteNotificationRow {
+    pubkey: String,
+    telegram: String,
+    created_at: i64,
+}
+
+impl From<SqliteNotificationRow> for DbNotification {
+    fn from(row: SqliteNotificationRow) -> Self {
+        DbNotification {
+            pubkey: row.pubkey,
+            telegram: row.telegram,
+            created_at: from_micros(row.created_at),
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct SqliteUploadSessionRow {
+    id: Uuid,
+    pubkey: String,
+    labels: Option<Json<Vec<String>>>,
+    expires_in: Option<i64>,
+    read_once: bool,
+    received: i64,
+    max_size: Option<i64>,
+    capability: Option<Json<CapabilityClaims>>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<SqliteUploadSessionRow> for DbUploadSession {
+    fn from(row: SqliteUploadSessionRow) -> Self {
+        DbUploadSession {
+            id: row.id,
+            pubkey: row.pubkey,
+            labels: row.labels,
+            expires_in: row.expires_in,
+            read_once: row.read_once,
+            received: row.received,
+            max_size: row.max_size,
+            capability: row.capability,
+            created_at: from_micros(row.created_at),
+            updated_at: from_micros(row.updated_at),
+        }
+    }
+}
+
+fn from_micros(micros: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(micros).unwrap_or_default()
+}
+
+/// SQLite implementation of [`Storage`].
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Open (creating if needed) the database at `database_url`.
+    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+        // Every connection to `:memory:` gets its own database, so keep exactly one alive
+        let pool = if database_url.contains(":memory:") {
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(options)
+                .await?
+        } else {
+            SqlitePoolOptions::new()
+                .max_connections(5)
+                .connect_with(options)
+                .await?
+        };
+        Ok(SqliteStorage { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn insert_item(&self, item: &NewItem, quota: Quota) -> Result<DbItem, StorageError> {
+        // SQLite serializes writers, so checking usage inside the INSERT makes
+        // the quota check atomic
+        let row = sqlx::query_as::<_, SqliteItemRow>(&format!(
+            "INSERT INTO items (id, pubkey, blob_key, size, labels, created_at, expires_at, read_once, format) \
+             SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?11 FROM \
+             (SELECT COUNT(*) AS item_count, COALESCE(SUM(size), 0) AS byte_count FROM items \
+              WHERE pubkey = ?2 AND (expires_at IS NULL OR expires_at > ?6)) \
+             WHERE (?9 IS NULL OR item_count < ?9) AND (?10 IS NULL OR byte_count + ?4 <= ?10) \
+             RETURNING {}",
+            ITEM_COLUMNS
+        ))
+        .bind(Uuid::new_v4())
+        .bind(&item.pubkey)
+        .bind(&item.blob_key)
+        .bind(item.size)
+        .bind(item.labels.as_ref().map(Json))
+        .bind(Utc::now().timestamp_micros())
+        .bind(item.expires_at.map(|t| t.timestamp_micros()))
+        .bind(item.read_once)
+        .bind(quota.max_items)
+        .bind(quota.max_bytes)
+        .bind(item.format.map(AgeFormat::as_str))
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(DbItem::from).ok_or(StorageError::QuotaExceeded)
+    }
+
+    async fn mailbox_usage(&self, pubkey: &str) -> Result<Usage, StorageError> {
+        let (items, bytes): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM items \
+             WHERE pubkey = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+        )
+        .bind(pubkey)
+        .bind(Utc::now().timestamp_micros())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Usage { items, bytes })
+    }
+
+    async fn list_items(
+        &self,
+        pubkey: &str,
+        filter: DeliveryFilter,
+        after: Option<PageKey>,
+        limit: u32,
+    ) -> Result<Vec<ItemMeta>, StorageError> {
+        let now = Utc::now().timestamp_micros();
+        let rows = if let Some((created_at, id)) = after {
+            sqlx::query_as::<_, SqliteItemMetaRow>(&format!(
+                "SELECT {} FROM items WHERE pubkey = ?1 AND (expires_at IS NULL OR expires_at > ?2){} AND (created_at < ?3 OR (created_at = ?3 AND id < ?4)) ORDER BY created_at DESC, id DESC LIMIT ?5",
+                ITEM_META_COLUMNS,
+                delivery_condition(filter)
+            ))
+            .bind(pubkey)
+            .bind(now)
+            .bind(created_at.timestamp_micros())
+            .bind(id)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?
+        } else {
+            sqlx::query_as::<_, SqliteItemMetaRow>(&format!(
+                "SELECT {} FROM items WHERE pubkey = ?1 AND (expires_at IS NULL OR expires_at > ?2){} ORDER BY created_at DESC, id DESC LIMIT ?3",
+                ITEM_META_COLUMNS,
+                delivery_condition(filter)
+            ))
+            .bind(pubkey)
+            .bind(now)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?
+        };
+        Ok(rows.into_iter().map(ItemMeta::from).collect())
+    }
+
+    async fn get_item(&self, id: Uuid) -> Result<Option<DbItem>, StorageError> {
+        let row = sqlx::query_as::<_, SqliteItemRow>(&format!(
+            "SELECT {} FROM items WHERE id = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+            ITEM_COLUMNS
+        ))
+        .bind(id)
+        .bind(Utc::now().timestamp_micros())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(DbItem::from))
+    }
+
+    async fn delete_item(&self, id: Uuid) -> Result<bool, StorageError> {
+        let result = sqlx::query("DELETE FROM items WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn count_blob_refs(&self, blob_key: &str) -> Result<i64, StorageError> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM items WHERE blob_key = ?1")
+            .bind(blob_key)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    async fn take_read_once_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+    ) -> Result<Option<DbItem>, StorageError> {
+        let row = sqlx::query_as::<_, SqliteItemRow>(&format!(
+            "DELETE FROM items WHERE id = ?1 AND pubkey = ?2 AND read_once \
+             AND (expires_at IS NULL OR expires_at > ?3) RETURNING {}",
+            ITEM_COLUMNS
+        ))
+        .bind(id)
+        .bind(pubkey)
+        .bind(Utc::now().timestamp_micros())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(DbItem::from))
+    }
+
+    async fn acknowledge_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        now: DateTime<Utc>,
+        purge_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        // SQLite's scalar MIN() returns NULL if any argument is NULL
+        let result = sqlx::query(
+            "UPDATE items SET delivered_at = COALESCE(delivered_at, ?3), expires_at = MIN(COALESCE(expires_at, ?4), ?4) \
+             WHERE id = ?1 AND pubkey = ?2 AND (expires_at IS NULL OR expires_at > ?3)",
+        )
+        .bind(id)
+        .bind(pubkey)
+        .bind(now.timestamp_micros())
+        .bind(purge_at.timestamp_micros())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn expired_items(
+        &self,
+        now: DateTime<Utc>,
+        created_before: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<DbItem>, StorageError> {
+        let rows = sqlx::query_as::<_, SqliteItemRow>(&format!(
+            "SELECT {} FROM items WHERE expires_at <= ?1 OR created_at < ?2 LIMIT ?3",
+            ITEM_COLUMNS
+        ))
+        .bind(now.timestamp_micros())
+        .bind(created_before.map(|t| t.timestamp_micros()))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(DbItem::from).collect())
+    }
+
+    async fn upsert_notification(
+        &self,
+        pubkey: &str,
+        telegram: &str,
+    ) -> Result<DbNotification, StorageError> {
+        let row = sqlx::query_as::<_, SqliteNotificationRow>(
+            "INSERT INTO notifications (pubkey, telegram, created_at) VALUES (?1, ?2, ?3) \
+             ON CONFLICT (pubkey) DO UPDATE SET telegram = excluded.telegram, created_at = excluded.created_at \
+             RETURNING *",
+        )
+        .bind(pubkey)
+        .bind(telegram)
+        .bind(Utc::now().timestamp_micros())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn get_notification(&self, pubkey: &str) -> Result<Option<DbNotification>, StorageError> {
+        let row = sqlx::query_as::<_, SqliteNotificationRow>(
+            "SELECT * FROM notifications WHERE pubkey = ?1",
+        )
+        .bind(pubkey)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(DbNotification::from))
+    }
+
+    async fn get_mailbox_settings(&self, pubkey: &str) -> Result<MailboxSettings, StorageError> {
+        let settings = sqlx::query_as::<_, MailboxSettings>(
+            "SELECT pow_difficulty, capability_required FROM mailbox_settings WHERE pubkey = ?1",
+        )
+        .bind(pubkey)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    async fn set_mailbox_settings(
+        &self,
+        pubkey: &str,
+        settings: &MailboxSettings,
+    ) -> Result<(), StorageError> {
+        sqlx::query(
+            "INSERT INTO mailbox_settings (pubkey, pow_difficulty, capability_required, updated_at) VALUES (?1, ?2, ?3, ?4) \
+             ON CONFLICT (pubkey) DO UPDATE SET pow_difficulty = excluded.pow_difficulty, \
+             capability_required = excluded.capability_required, updated_at = excluded.updated_at",
+        )
+        .bind(pubkey)
+        .bind(settings.pow_difficulty)
+        .bind(settings.capability_required)
+        .bind(Utc::now().timestamp_micros())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn consume_capability(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        max_uses: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let row: Option<(i64,)> = sqlx::query_as(
+            "INSERT INTO capability_uses (id, pubkey, uses, expires_at) VALUES (?1, ?2, 1, ?4) \
+             ON CONFLICT (id) DO UPDATE SET uses = uses + 1 WHERE uses < ?3 RETURNING uses",
+        )
+        .bind(id)
+        .bind(pubkey)
+        .bind(max_uses)
+        .bind(expires_at.timestamp_micros())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.is_some_and(|(uses,)| uses <= max_uses))
+    }
+
+    async fn release_capability(&self, id: Uuid) -> Result<(), StorageError> {
+        sqlx::query("UPDATE capability_uses SET uses = uses - 1 WHERE id = ?1 AND uses > 0")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_expired_capabilities(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        let result = sqlx::query("DELETE FROM capability_uses WHERE expires_at <= ?1")
+            .bind(now.timestamp_micros())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn unhashed_pubkeys(&self) -> Result<Vec<String>, StorageError> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT pubkey FROM unhashed_mailboxes")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|(pubkey,)| pubkey).collect())
+    }
+
+    async fn rename_mailbox(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+        for statement in [
+            "UPDATE items SET pubkey = ?2 WHERE pubkey = ?1",
+            "UPDATE upload_sessions SET pubkey = ?2 WHERE pubkey = ?1",
+            "UPDATE capability_uses SET pubkey = ?2 WHERE pubkey = ?1",
+            "INSERT INTO notifications (pubkey, telegram, created_at) \
+             SELECT ?2, telegram, created_at FROM notifications WHERE pubkey = ?1 \
+             ON CONFLICT (pubkey) DO NOTHING",
+            "DELETE FROM notifications WHERE pubkey = ?1",
+            "INSERT INTO mailbox_settings (pubkey, pow_difficulty, capability_required, updated_at) \
+             SELECT ?2, pow_difficulty, capability_required, updated_at FROM mailbox_settings \
+             WHERE pubkey = ?1 ON CONFLICT (pubkey) DO NOTHING",
+            "DELETE FROM mailbox_settings WHERE pubkey = ?1",
+        ] {
+            sqlx::query(statement)
+                .bind(from)
+                .bind(to)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("DELETE FROM unhashed_mailboxes WHERE pubkey = ?1")
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn skip_unhashed_pubkey(&self, pubkey: &str) -> Result<(), StorageError> {
+        sqlx::query("DELETE FROM unhashed_mailboxes WHERE pubkey = ?1")
+            .bind(pubkey)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn inline_ciphertexts(&self, limit: u32) -> Result<Vec<(Uuid, Vec<u8>)>, StorageError> {
+        Ok(sqlx::query_as(
+            "SELECT id, ciphertext FROM items WHERE blob_key IS NULL AND ciphertext IS NOT NULL LIMIT ?1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn set_blob_ref(&self, id: Uuid, blob_key: &str, size: i64) -> Result<(), StorageError> {
+        sqlx::query("UPDATE items SET blob_key = ?2, size = ?3, ciphertext = NULL WHERE id = ?1")
+            .bind(id)
+            .bind(blob_key)
+            .bind(size)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_upload_session(
+        &self,
+        session: &NewUploadSession,
+    ) -> Result<DbUploadSession, StorageError> {
+        let row = sqlx::query_as::<_, SqliteUploadSessionRow>(
+            "INSERT INTO upload_sessions (id, pubkey, labels, expires_in, read_once, max_size, capability, received, created_at, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0, ?8, ?8) RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(&session.pubkey)
+        .bind(session.labels.as_ref().map(Json))
+        .bind(session.expires_in)
+        .bind(session.read_once)
+        .bind(session.max_size)
+        .bind(session.capability.as_ref().map(Json))
+        .bind(Utc::now().timestamp_micros())
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn get_upload_session(&self, id: Uuid) -> Result<Option<DbUploadSession>, StorageError> {
+        let row = sqlx::query_as::<_, SqliteUploadSessionRow>(
+            "SELECT * FROM upload_sessions WHERE id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(DbUploadSession::from))
+    }
+
+    async fn advance_upload_session(
+        &self,
+        id: Uuid,
+        from: i64,
+        to: i64,
+    ) -> Result<bool, StorageError> {
+        let result = sqlx::query(
+            "UPDATE upload_sessions SET received = ?3, updated_at = ?4 WHERE id = ?1 AND received = ?2",
+        )
+        .bind(id)
+        .bind(from)
+        .bind(to)
+        .bind(Utc::now().timestamp_micros())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_upload_session(&self, id: Uuid) -> Result<bool, StorageError> {
+        let result = sqlx::query("DELETE FROM upload_sessions WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn stale_upload_sessions(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>, StorageError> {
+        let rows: Vec<(Uuid,)> =
+            sqlx::query_as("SELECT id FROM upload_sessions WHERE updated_at < ?1")
+                .bind(before.timestamp_micros())
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+}
+
+/// SQLite counterparts of `crate::db::MIGRATIONS`; same numbering.
+const MIGRATIONS: &[&str] = &[
+    // 1: ciphertext bodies move to the blob store. SQLite cannot drop a NOT NULL
+    // constraint in place, so the table is rebuilt.
+    r#"
+    CREATE TABLE items_v1 (
+        id BLOB PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        ciphertext BLOB,
+        blob_key TEXT,
+        size INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL
+    );
+    INSERT INTO items_v1 (id, pubkey, ciphertext, created_at)
+        SELECT id, pubkey, ciphertext, created_at FROM items;
+    DROP TABLE items;
+    ALTER TABLE items_v1 RENAME TO items;
+    "#,
+    // 2: optional sender labels (JSON text) and the /retrieve pagination index
+    r#"
+    ALTER TABLE items ADD COLUMN labels TEXT;
+    CREATE INDEX IF NOT EXISTS items_pubkey_created_at_id_idx
+        ON items (pubkey, created_at DESC, id DESC);
+    "#,
+    // 3: resumable upload sessions
+    r#"
+    CREATE TABLE upload_sessions (
+        id BLOB PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        labels TEXT,
+        received INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    "#,
+    // 4: per-item expiry (microseconds since epoch)
+    r#"
+    ALTER TABLE items ADD COLUMN expires_at INTEGER;
+    ALTER TABLE upload_sessions ADD COLUMN expires_in INTEGER;
+    CREATE INDEX IF NOT EXISTS items_expires_at_idx ON items (expires_at);
+    "#,
+    // 5: burn-after-reading items
+    r#"
+    ALTER TABLE items ADD COLUMN read_once INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE upload_sessions ADD COLUMN read_once INTEGER NOT NULL DEFAULT 0;
+    "#,
+    // 6: recipient acknowledgements (microseconds since epoch)
+    r#"
+    ALTER TABLE items ADD COLUMN delivered_at INTEGER;
+    "#,
+    // 7: per-mailbox settings chosen by the recipient
+    r#"
+    CREATE TABLE mailbox_settings (
+        pubkey TEXT PRIMARY KEY,
+        pow_difficulty INTEGER,
+        updated_at INTEGER NOT NULL
+    );
+    "#,
+    // 8: recipient-issued upload capabilities (expires_at in microseconds since epoch)
+    r#"
+    ALTER TABLE mailbox_settings ADD COLUMN capability_required INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE upload_sessions ADD COLUMN max_size INTEGER;
+    CREATE TABLE capability_uses (
+        id BLOB PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        uses INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    "#,
+    // 9: encoding of validated age uploads
+    r#"
+    ALTER TABLE items ADD COLUMN format TEXT;
+    "#,
+    // 10: raw pubkeys to rewrite under `mailbox::storage_key`, recorded once
+    // so that later startups do not scan every table
+    r#"
+    CREATE TABLE unhashed_mailboxes (pubkey TEXT PRIMARY KEY);
+    INSERT INTO unhashed_mailboxes (pubkey)
+        SELECT pubkey FROM items
+        UNION SELECT pubkey FROM upload_sessions
+        UNION SELECT pubkey FROM notifications
+        UNION SELECT pubkey FROM mailbox_settings
+        UNION SELECT pubkey FROM capability_uses;
+    "#,
+    // 11: capability of a resumable upload, counted when it is finalized
+    r#"
+    ALTER TABLE upload_sessions ADD COLUMN capability TEXT;
+    "#,
+];
+
+/// Run SQLite migrations: the same schema as [`crate::db::db_migrate`].
+pub async fn db_migrate(storage: &SqliteStorage) -> Result<(), sqlx::Error> {
+    let pool = &storage.pool;
+    // Create schema_version table if it doesn't exist
+    pool.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER NOT NULL
+        )
+    "#,
+    )
+    .await?;
+    // Only insert version 0 if schema_version is empty
+    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    if count.0 == 0 {
+        sqlx::query("INSERT INTO schema_version (version) VALUES (0)")
+            .execute(pool)
+            .await?;
+    }
+    // Create items table if it doesn't exist (created_at in microseconds since epoch)
+    pool.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS items (
+            id BLOB PRIMARY KEY,
+            pubkey TEXT NOT NULL,
+            ciphertext BLOB NOT NULL,
+            created_at INTEGER NOT NULL
+        )
+    "#,
+    )
+    .await?;
+    // Create notifications table if it doesn't exist (one Telegram target per pubkey)
+    pool.execute(
+        r#"
+        CREATE TABLE IF NOT EXISTS notifications (
+            pubkey TEXT PRIMARY KEY,
+            telegram TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )
+    "#,
+    )
+    .await?;
+    // Apply pending schema migrations, each in its own transaction
+    let (version,): (i64,) = sqlx::query_as("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let mut tx = pool.begin().await?;
+        tx.execute(*migration).await?;
+        sqlx::query("UPDATE schema_version SET version = ?1")
+            .bind(index as i64 + 1)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,443 @@
+use super::*;
+use crate::blob::tests::read_blob;
+use crate::db::NewItem;
+
+async fn setup_db() -> SqliteStorage {
+    let storage = SqliteStorage::connect("sqlite::memory:")
+        .await
+        .expect("Failed to open SQLite");
+    db_migrate(&storage).await.expect("Migration failed");
+    storage
+}
+
+#[tokio::test]
+async fn test_insert_and_get_item() {
+    let storage = setup_db().await;
+    let item = storage
+        .insert_item(
+            &NewItem::new("test_pubkey", "test_blob_key", 15),
+            Quota::default(),
+        )
+        .await
+        .unwrap();
+    let fetched = storage.get_item(item.id).await.unwrap().unwrap();
+    assert_eq!(fetched.id, item.id);
+    assert_eq!(fetched.pubkey, "test_pubkey");
+    assert_eq!(fetched.blob_key, "test_blob_key");
+    assert_eq!(fetched.size, 15);
+    assert_eq!(fetched.created_at, item.created_at);
+    assert!(storage.delete_item(item.id).await.unwrap());
+    assert!(storage.get_item(item.id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_list_items_keyset_pagination() {
+    let storage = setup_db().await;
+    let mut inserted = Vec::new();
+    for i in 0..5 {
+        inserted.push(
+            storage
+                .insert_item(&NewItem::new("pk", "abcd", i), Quota::default())
+                .await
+                .unwrap()
+                .id,
+        );
+    }
+    storage
+        .insert_item(&NewItem::new("other", "abcd", 1), Quota::default())
+        .await
+        .unwrap();
+
+    let first = storage
+        .list_items("pk", DeliveryFilter::All, None, 3)
+        .await
+        .unwrap();
+    let last = first.last().unwrap();
+    let second = storage
+        .list_items(
+            "pk",
+            DeliveryFilter::All,
+            Some((last.created_at, last.id)),
+            3,
+        )
+        .await
+        .unwrap();
+    assert_eq!(first.len(), 3);
+    assert_eq!(second.len(), 2);
+
+    let mut listed: Vec<Uuid> = first.iter().chain(second.iter()).map(|i| i.id).collect();
+    let all: Vec<_> = first.iter().chain(second.iter()).collect();
+    for pair in all.windows(2) {
+        assert!((pair[0].created_at, pair[0].id) > (pair[1].created_at, pair[1].id));
+    }
+    listed.sort();
+    inserted.sort();
+    assert_eq!(listed, inserted);
+}
+
+#[tokio::test]
+async fn test_upsert_notification() {
+    let storage = setup_db().await;
+    assert!(storage.get_notification("pk").await.unwrap().is_none());
+    storage.upsert_notification("pk", "@first").await.unwrap();
+    storage.upsert_notification("pk", "@second").await.unwrap();
+    let registration = storage.get_notification("pk").await.unwrap().unwrap();
+    assert_eq!(registration.telegram, "@second");
+}
+
+#[tokio::test]
+async fn test_migrate_inline_ciphertexts() {
+    // Build a pre-blob-store (version 0) database holding one inline ciphertext
+    let storage = SqliteStorage::connect("sqlite::memory:").await.unwrap();
+    storage
+        .pool
+        .execute(
+            r#"
+            CREATE TABLE schema_version (version INTEGER NOT NULL);
+            INSERT INTO schema_version (version) VALUES (0);
+            CREATE TABLE items (
+                id BLOB PRIMARY KEY,
+                pubkey TEXT NOT NULL,
+                ciphertext BLOB NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+        "#,
+        )
+        .await
+        .unwrap();
+    let id = Uuid::new_v4();
+    sqlx::query("INSERT INTO items (id, pubkey, ciphertext, created_at) VALUES (?1, 'pk', ?2, 0)")
+        .bind(id)
+        .bind(&b"legacy"[..])
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+
+    db_migrate(&storage).await.unwrap();
+    let blobs = crate::blob::MemoryBlobStore::new();
+    let migrated = crate::blob::migrate_inline_ciphertexts(&storage, &blobs)
+        .await
+        .unwrap();
+    assert_eq!(migrated, 1);
+
+    let item = storage.get_item(id).await.unwrap().unwrap();
+    assert_eq!(item.size, 6);
+    assert_eq!(read_blob(&blobs, &item.blob_key).await.unwrap(), b"legacy");
+    assert!(storage.inline_ciphertexts(10).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_list_items_returns_labels() {
+    let storage = setup_db().await;
+    let mut item = NewItem::new("pk", "abcd", 4);
+    item.labels = Some(vec!["invoice".to_string(), "urgent".to_string()]);
+    item.format = Some(AgeFormat::Binary);
+    storage.insert_item(&item, Quota::default()).await.unwrap();
+    let listed = storage
+        .list_items("pk", DeliveryFilter::All, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(listed[0].size, 4);
+    assert_eq!(listed[0].labels.as_ref().unwrap().0, ["invoice", "urgent"]);
+    assert_eq!(listed[0].format.as_deref(), Some("binary"));
+}
+
+#[tokio::test]
+async fn test_upload_sessions() {
+    let storage = setup_db().await;
+    let mut new_session = NewUploadSession::new("pk");
+    new_session.labels = Some(vec!["invoice".to_string()]);
+    let session = storage.create_upload_session(&new_session).await.unwrap();
+    assert!(
+        storage
+            .advance_upload_session(session.id, 0, 7)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !storage
+            .advance_upload_session(session.id, 0, 7)
+            .await
+            .unwrap()
+    );
+    let fetched = storage
+        .get_upload_session(session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fetched.received, 7);
+    assert_eq!(fetched.labels.unwrap().0, vec!["invoice"]);
+
+    let later = fetched.updated_at + chrono::Duration::seconds(1);
+    assert_eq!(
+        storage.stale_upload_sessions(later).await.unwrap(),
+        vec![session.id]
+    );
+    assert!(storage.delete_upload_session(session.id).await.unwrap());
+    assert!(
+        storage
+            .get_upload_session(session.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_expired_items_are_hidden() {
+    let storage = setup_db().await;
+    let mut expired = NewItem::new("pk", "abcd", 4);
+    expired.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+    let expired = storage
+        .insert_item(&expired, Quota::default())
+        .await
+        .unwrap();
+    let mut fresh = NewItem::new("pk", "abcd", 4);
+    fresh.expires_at = Some(Utc::now() + chrono::Duration::hours(1));
+    let fresh = storage.insert_item(&fresh, Quota::default()).await.unwrap();
+
+    assert!(storage.get_item(expired.id).await.unwrap().is_none());
+    let listed = storage
+        .list_items("pk", DeliveryFilter::All, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, fresh.id);
+    assert!(listed[0].expires_at.is_some());
+
+    let purgeable = storage.expired_items(Utc::now(), None, 10).await.unwrap();
+    assert_eq!(purgeable.len(), 1);
+    assert_eq!(purgeable[0].id, expired.id);
+    // With a global max age, old items are purgeable whatever their own expiry
+    let later = Utc::now() + chrono::Duration::seconds(1);
+    assert_eq!(
+        storage
+            .expired_items(Utc::now(), Some(later), 10)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_take_read_once_item() {
+    let storage = setup_db().await;
+    let mut new_item = NewItem::new("pk", "abcd", 4);
+    new_item.read_once = true;
+    let item = storage
+        .insert_item(&new_item, Quota::default())
+        .await
+        .unwrap();
+    assert!(item.read_once);
+    assert!(
+        storage
+            .list_items("pk", DeliveryFilter::All, None, 10)
+            .await
+            .unwrap()[0]
+            .read_once
+    );
+
+    assert!(
+        storage
+            .take_read_once_item(item.id, "other")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let taken = storage
+        .take_read_once_item(item.id, "pk")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(taken.id, item.id);
+    assert!(
+        storage
+            .take_read_once_item(item.id, "pk")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(storage.get_item(item.id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_acknowledge_item() {
+    let storage = setup_db().await;
+    let item = storage
+        .insert_item(&NewItem::new("pk", "abcd", 4), Quota::default())
+        .await
+        .unwrap();
+    let other = storage
+        .insert_item(&NewItem::new("pk", "abcd", 4), Quota::default())
+        .await
+        .unwrap();
+    let now = Utc::now();
+    let purge_at = now + chrono::Duration::hours(1);
+    assert!(
+        !storage
+            .acknowledge_item(item.id, "other", now, purge_at)
+            .await
+            .unwrap()
+    );
+    assert!(
+        storage
+            .acknowledge_item(item.id, "pk", now, purge_at)
+            .await
+            .unwrap()
+    );
+    // A later acknowledgement keeps the first delivery time and earlier purge
+    let later = now + chrono::Duration::minutes(1);
+    assert!(
+        storage
+            .acknowledge_item(item.id, "pk", later, later + chrono::Duration::hours(1))
+            .await
+            .unwrap()
+    );
+
+    let delivered = storage
+        .list_items("pk", DeliveryFilter::Delivered, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].id, item.id);
+    assert_eq!(
+        delivered[0].delivered_at.unwrap().timestamp_micros(),
+        now.timestamp_micros()
+    );
+    assert_eq!(
+        delivered[0].expires_at.unwrap().timestamp_micros(),
+        purge_at.timestamp_micros()
+    );
+    let undelivered = storage
+        .list_items("pk", DeliveryFilter::Undelivered, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(undelivered.len(), 1);
+    assert_eq!(undelivered[0].id, other.id);
+    assert_eq!(
+        storage
+            .expired_items(purge_at, None, 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_insert_item_within_quota() {
+    let storage = setup_db().await;
+    let quota = Quota {
+        max_items: Some(2),
+        max_bytes: Some(10),
+    };
+    storage
+        .insert_item(&NewItem::new("pk", "a", 6), quota)
+        .await
+        .unwrap();
+    assert!(matches!(
+        storage
+            .insert_item(&NewItem::new("pk", "b", 5), quota)
+            .await,
+        Err(StorageError::QuotaExceeded)
+    ));
+    storage
+        .insert_item(&NewItem::new("pk", "b", 4), quota)
+        .await
+        .unwrap();
+    assert!(matches!(
+        storage
+            .insert_item(&NewItem::new("pk", "c", 0), quota)
+            .await,
+        Err(StorageError::QuotaExceeded)
+    ));
+    // Expired items no longer count towards the quota
+    let mut expired = NewItem::new("other", "c", 10);
+    expired.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+    storage
+        .insert_item(&expired, Quota::default())
+        .await
+        .unwrap();
+    storage
+        .insert_item(&NewItem::new("other", "d", 10), quota)
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.mailbox_usage("pk").await.unwrap(),
+        Usage {
+            items: 2,
+            bytes: 10
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_mailbox_settings() {
+    let storage = setup_db().await;
+    assert_eq!(
+        storage.get_mailbox_settings("pk").await.unwrap(),
+        MailboxSettings::default()
+    );
+    for difficulty in [Some(12), None] {
+        let settings = MailboxSettings {
+            pow_difficulty: difficulty,
+            capability_required: difficulty.is_some(),
+        };
+        storage.set_mailbox_settings("pk", &settings).await.unwrap();
+        assert_eq!(storage.get_mailbox_settings("pk").await.unwrap(), settings);
+    }
+}
+
+#[tokio::test]
+async fn test_consume_capability() {
+    let storage = setup_db().await;
+    let id = Uuid::new_v4();
+    let expires_at = Utc::now() + chrono::Duration::hours(1);
+    for _ in 0..2 {
+        assert!(
+            storage
+                .consume_capability(id, "pk", 2, expires_at)
+                .await
+                .unwrap()
+        );
+    }
+    assert!(
+        !storage
+            .consume_capability(id, "pk", 2, expires_at)
+            .await
+            .unwrap()
+    );
+    // A released use can be spent again
+    storage.release_capability(id).await.unwrap();
+    assert!(
+        storage
+            .consume_capability(id, "pk", 2, expires_at)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        storage
+            .delete_expired_capabilities(Utc::now())
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        storage
+            .delete_expired_capabilities(expires_at)
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_stored_mailbox_ids() {
+    let storage = setup_db().await;
+    let record = sqlx::query(crate::mailbox::tests::RECORD_UNHASHED).execute(&storage.pool);
+    crate::mailbox::tests::check_migrate_stored_ids(&storage, async {
+        record.await.unwrap();
+    })
+    .await;
+}
@@ -1,9 +1,22 @@
-use crate::db::DbItem;
+use crate::agefile::AgeFormat;
+use crate::blob::tests::read_blob;
+use crate::blob::{MemoryBlobStore, migrate_inline_ciphertexts};
+use crate::db::{
+    DbCapabilityUses, DbItem, DbNotification, DbUploadSession, MailboxSettings, NewItem,
+    NewUploadSession, PgStorage,
+};
+use crate::storage::{DeliveryFilter, Quota};
 use dotenvy::dotenv;
 use sqlx::{PgPool, postgres::PgPoolOptions};
 use std::env;
+use tokio::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
 
-async fn setup_db() -> PgPool {
+// Tests share one database and drop its tables, so they must not run concurrently
+static DB_LOCK: Mutex<()> = Mutex::const_new(());
+
+async fn setup_db() -> (PgPool, MutexGuard<'static, ()>) {
+    let guard = DB_LOCK.lock().await;
     dotenv().ok();
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
     let pool = PgPoolOptions::new()
@@ -12,10 +25,30 @@ async fn setup_db() -> PgPool {
         .await
         .expect("Failed to connect to Postgres");
     // Drop tables for a clean start
+    sqlx::query("DROP TABLE IF EXISTS upload_sessions")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("DROP TABLE IF EXISTS mailbox_settings")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("DROP TABLE IF EXISTS capability_uses")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("DROP TABLE IF EXISTS notifications")
+        .execute(&pool)
+        .await
+        .unwrap();
     sqlx::query("DROP TABLE IF EXISTS items")
         .execute(&pool)
         .await
         .unwrap();
+    sqlx::query("DROP TABLE IF EXISTS unhashed_mailboxes")
+        .execute(&pool)
+        .await
+        .unwrap();
     sqlx::query("DROP TABLE IF EXISTS schema_version")
         .execute(&pool)
         .await
@@ -24,17 +57,20 @@ async fn setup_db() -> PgPool {
     crate::db::db_migrate(&pool)
         .await
         .expect("Migration failed");
-    pool
+    (pool, guard)
 }
 
 #[tokio::test]
 async fn test_insert_and_get_item() {
-    let pool = setup_db().await;
+    let (pool, _guard) = setup_db().await;
     let pubkey = "test_pubkey";
-    let ciphertext = b"test_ciphertext";
-    let item = DbItem::insert(&pool, pubkey, ciphertext).await.unwrap();
+    let blob_key = "test_blob_key";
+    let item = DbItem::insert(&pool, &NewItem::new(pubkey, blob_key, 15))
+        .await
+        .unwrap();
     assert_eq!(item.pubkey, pubkey);
-    assert_eq!(item.ciphertext, ciphertext);
+    assert_eq!(item.blob_key, blob_key);
+    assert_eq!(item.size, 15);
 
     // get by id
     let fetched = DbItem::get_item_by_id(&pool, item.id)
@@ -43,19 +79,329 @@ async fn test_insert_and_get_item() {
         .unwrap();
     assert_eq!(fetched.id, item.id);
     assert_eq!(fetched.pubkey, pubkey);
-    assert_eq!(fetched.ciphertext, ciphertext);
+    assert_eq!(fetched.blob_key, blob_key);
 }
 
 #[tokio::test]
-async fn test_get_items_for_pubkey() {
-    let pool = setup_db().await;
+async fn test_get_page_for_pubkey() {
+    let (pool, _guard) = setup_db().await;
     let pubkey = "test_pubkey2";
-    let ciphertext1 = b"cipher1";
-    let ciphertext2 = b"cipher2";
-    DbItem::insert(&pool, pubkey, ciphertext1).await.unwrap();
-    DbItem::insert(&pool, pubkey, ciphertext2).await.unwrap();
-    let items = DbItem::get_items_for_pubkey(&pool, pubkey).await.unwrap();
+    let blob_key1 = "blob1";
+    let blob_key2 = "blob2";
+    let item1 = DbItem::insert(&pool, &NewItem::new(pubkey, blob_key1, 7))
+        .await
+        .unwrap();
+    let mut labelled = NewItem::new(pubkey, blob_key2, 9);
+    labelled.labels = Some(vec!["invoice".to_string()]);
+    labelled.format = Some(AgeFormat::Armored);
+    let item2 = DbItem::insert(&pool, &labelled).await.unwrap();
+    let items = DbItem::get_page_for_pubkey(&pool, pubkey, DeliveryFilter::All, None, 10)
+        .await
+        .unwrap();
     assert_eq!(items.len(), 2);
-    assert!(items.iter().any(|i| i.ciphertext == ciphertext1));
-    assert!(items.iter().any(|i| i.ciphertext == ciphertext2));
+    assert!(
+        items
+            .iter()
+            .any(|i| i.id == item1.id && i.labels.is_none() && i.format.is_none())
+    );
+    assert!(items.iter().any(|i| i.id == item2.id
+        && i.size == 9
+        && i.format.as_deref() == Some("armored")
+        && i.labels.as_ref().map(|l| l.0.clone()) == Some(vec!["invoice".to_string()])));
+}
+
+#[tokio::test]
+async fn test_migrate_inline_ciphertexts() {
+    let (pool, _guard) = setup_db().await;
+    // Rows written before the blob store only carry an inline ciphertext
+    let id = Uuid::new_v4();
+    sqlx::query(
+        "INSERT INTO items (id, pubkey, ciphertext, created_at) VALUES ($1, 'legacy', $2, now())",
+    )
+    .bind(id)
+    .bind(&b"legacy"[..])
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let storage = PgStorage::new(pool.clone());
+    let blobs = MemoryBlobStore::new();
+    let migrated = migrate_inline_ciphertexts(&storage, &blobs).await.unwrap();
+    assert_eq!(migrated, 1);
+
+    let item = DbItem::get_item_by_id(&pool, id).await.unwrap().unwrap();
+    assert_eq!(item.size, 6);
+    assert_eq!(read_blob(&blobs, &item.blob_key).await.unwrap(), b"legacy");
+    assert!(
+        DbItem::get_inline_ciphertexts(&pool, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_notification() {
+    let (pool, _guard) = setup_db().await;
+    let pubkey = "test_pubkey3";
+    assert!(
+        DbNotification::get_for_pubkey(&pool, pubkey)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    DbNotification::upsert(&pool, pubkey, "@first")
+        .await
+        .unwrap();
+    DbNotification::upsert(&pool, pubkey, "@second")
+        .await
+        .unwrap();
+    let registration = DbNotification::get_for_pubkey(&pool, pubkey)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(registration.telegram, "@second");
+}
+
+#[tokio::test]
+async fn test_upload_session_lifecycle() {
+    let (pool, _guard) = setup_db().await;
+    let mut session_options = NewUploadSession::new("test_pubkey4");
+    session_options.expires_in = Some(60);
+    let session = DbUploadSession::create(&pool, &session_options)
+        .await
+        .unwrap();
+    assert!(
+        DbUploadSession::advance(&pool, session.id, 0, 9)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !DbUploadSession::advance(&pool, session.id, 0, 9)
+            .await
+            .unwrap()
+    );
+    let fetched = DbUploadSession::get_by_id(&pool, session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fetched.received, 9);
+
+    let later = fetched.updated_at + chrono::Duration::seconds(1);
+    assert_eq!(
+        DbUploadSession::get_stale(&pool, later).await.unwrap(),
+        vec![session.id]
+    );
+    assert!(
+        DbUploadSession::delete_by_id(&pool, session.id)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !DbUploadSession::delete_by_id(&pool, session.id)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_expired_items_are_hidden() {
+    let (pool, _guard) = setup_db().await;
+    let mut expired = NewItem::new("test_pubkey5", "abcd", 4);
+    expired.expires_at = Some(chrono::Utc::now() - chrono::Duration::seconds(1));
+    let expired = DbItem::insert(&pool, &expired).await.unwrap();
+    let fresh = DbItem::insert(&pool, &NewItem::new("test_pubkey5", "abcd", 4))
+        .await
+        .unwrap();
+
+    assert!(
+        DbItem::get_item_by_id(&pool, expired.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let listed = DbItem::get_page_for_pubkey(&pool, "test_pubkey5", DeliveryFilter::All, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id, fresh.id);
+    let purgeable = DbItem::get_expired(&pool, chrono::Utc::now(), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(purgeable.len(), 1);
+    assert_eq!(purgeable[0].id, expired.id);
+}
+
+#[tokio::test]
+async fn test_take_read_once() {
+    let (pool, _guard) = setup_db().await;
+    let mut new_item = NewItem::new("test_pubkey6", "abcd", 4);
+    new_item.read_once = true;
+    let item = DbItem::insert(&pool, &new_item).await.unwrap();
+    assert!(
+        DbItem::take_read_once(&pool, item.id, "other")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        DbItem::take_read_once(&pool, item.id, "test_pubkey6")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        DbItem::take_read_once(&pool, item.id, "test_pubkey6")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_acknowledge() {
+    let (pool, _guard) = setup_db().await;
+    let item = DbItem::insert(&pool, &NewItem::new("test_pubkey7", "abcd", 4))
+        .await
+        .unwrap();
+    DbItem::insert(&pool, &NewItem::new("test_pubkey7", "abcd", 4))
+        .await
+        .unwrap();
+    let now = chrono::Utc::now();
+    let purge_at = now + chrono::Duration::hours(1);
+    assert!(
+        !DbItem::acknowledge(&pool, item.id, "other", now, purge_at)
+            .await
+            .unwrap()
+    );
+    assert!(
+        DbItem::acknowledge(&pool, item.id, "test_pubkey7", now, purge_at)
+            .await
+            .unwrap()
+    );
+    let delivered =
+        DbItem::get_page_for_pubkey(&pool, "test_pubkey7", DeliveryFilter::Delivered, None, 10)
+            .await
+            .unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].id, item.id);
+    assert!(delivered[0].delivered_at.is_some());
+    assert!(delivered[0].expires_at.is_some());
+    let undelivered =
+        DbItem::get_page_for_pubkey(&pool, "test_pubkey7", DeliveryFilter::Undelivered, None, 10)
+            .await
+            .unwrap();
+    assert_eq!(undelivered.len(), 1);
+    assert_ne!(undelivered[0].id, item.id);
+}
+
+#[tokio::test]
+async fn test_insert_within_quota_is_atomic() {
+    let (_pool, _guard) = setup_db().await;
+    // A pool of its own, so inserts really run concurrently
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    let quota = Quota {
+        max_items: Some(3),
+        max_bytes: None,
+    };
+    let inserts = (0..10).map(|_| {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            DbItem::insert_within_quota(&pool, &NewItem::new("test_pubkey8", "abcd", 4), quota)
+                .await
+                .unwrap()
+        })
+    });
+    let mut stored = 0;
+    for insert in inserts.collect::<Vec<_>>() {
+        if insert.await.unwrap().is_some() {
+            stored += 1;
+        }
+    }
+    assert_eq!(stored, 3);
+    let usage = DbItem::usage_for_pubkey(&pool, "test_pubkey8")
+        .await
+        .unwrap();
+    assert_eq!((usage.items, usage.bytes), (3, 12));
+}
+
+#[tokio::test]
+async fn test_mailbox_settings() {
+    let (pool, _guard) = setup_db().await;
+    assert_eq!(
+        MailboxSettings::get_for_pubkey(&pool, "test_pubkey9")
+            .await
+            .unwrap(),
+        MailboxSettings::default()
+    );
+    for difficulty in [Some(12), None] {
+        let settings = MailboxSettings {
+            pow_difficulty: difficulty,
+            capability_required: difficulty.is_some(),
+        };
+        MailboxSettings::upsert(&pool, "test_pubkey9", &settings)
+            .await
+            .unwrap();
+        assert_eq!(
+            MailboxSettings::get_for_pubkey(&pool, "test_pubkey9")
+                .await
+                .unwrap(),
+            settings
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_consume_capability() {
+    let (pool, _guard) = setup_db().await;
+    let id = Uuid::new_v4();
+    let expires_at = chrono::Utc::now() + chrono::Duration::hours(1);
+    for _ in 0..2 {
+        assert!(
+            DbCapabilityUses::consume(&pool, id, "test_pubkey10", 2, expires_at)
+                .await
+                .unwrap()
+        );
+    }
+    assert!(
+        !DbCapabilityUses::consume(&pool, id, "test_pubkey10", 2, expires_at)
+            .await
+            .unwrap()
+    );
+    // A released use can be spent again
+    DbCapabilityUses::release(&pool, id).await.unwrap();
+    assert!(
+        DbCapabilityUses::consume(&pool, id, "test_pubkey10", 2, expires_at)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        DbCapabilityUses::delete_expired(&pool, chrono::Utc::now())
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        DbCapabilityUses::delete_expired(&pool, expires_at)
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_stored_mailbox_ids() {
+    let (pool, _guard) = setup_db().await;
+    let storage = PgStorage::new(pool.clone());
+    crate::mailbox::tests::check_migrate_stored_ids(&storage, async {
+        sqlx::query(crate::mailbox::tests::RECORD_UNHASHED)
+            .execute(&pool)
+            .await
+            .unwrap();
+    })
+    .await;
 }
@@ -1 +1,91 @@
-// Custom error types and handling will go here
+use std::fmt;
+
+/// Errors returned by storage backends.
+#[derive(Debug)]
+pub enum StorageError {
+    Database(sqlx::Error),
+    Blob(BlobError),
+    QuotaExceeded, // the mailbox is full
+    Full,          // a bounded in-memory store has no room left
+    Config(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Database(e) => write!(f, "{}", e),
+            StorageError::Blob(e) => write!(f, "{}", e),
+            StorageError::QuotaExceeded => write!(f, "Mailbox quota exceeded"),
+            StorageError::Full => write!(f, "Store is full"),
+            StorageError::Config(e) => write!(f, "Storage configuration error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(e: sqlx::Error) -> Self {
+        StorageError::Database(e)
+    }
+}
+
+impl From<BlobError> for StorageError {
+    fn from(e: BlobError) -> Self {
+        StorageError::Blob(e)
+    }
+}
+
+/// Errors loading the JWT signing keys.
+#[derive(Debug)]
+pub enum KeyringError {
+    Io(std::io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyringError::Io(e) => write!(f, "Failed to read keys file: {}", e),
+            KeyringError::Invalid(e) => write!(f, "Invalid signing keys: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KeyringError {}
+
+impl From<std::io::Error> for KeyringError {
+    fn from(e: std::io::Error) -> Self {
+        KeyringError::Io(e)
+    }
+}
+
+/// Errors returned by blob stores.
+#[derive(Debug)]
+pub enum BlobError {
+    Io(std::io::Error),
+    Http(String),
+    InvalidKey(String),
+    Config(String),
+    TooLarge,
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::Io(e) => write!(f, "Blob I/O error: {}", e),
+            BlobError::Http(e) => write!(f, "Blob store error: {}", e),
+            BlobError::InvalidKey(key) => write!(f, "Invalid blob key: {}", key),
+            BlobError::Config(e) => write!(f, "Blob store configuration error: {}", e),
+            BlobError::TooLarge => write!(f, "Blob exceeds size limit"),
+        }
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+impl From<std::io::Error> for BlobError {
+    fn from(e: std::io::Error) -> Self {
+        BlobError::Io(e)
+    }
+}
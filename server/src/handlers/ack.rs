@@ -0,0 +1,78 @@
+use crate::AppState;
+use crate::auth::verify_jwt_from_header;
+use crate::mailbox::storage_key;
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const MAX_ACK_IDS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct AckRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct AckResponse {
+    pub acknowledged: Vec<Uuid>,
+    pub purge_at: DateTime<Utc>, // acknowledged items are deleted after this time
+}
+
+/// `POST /ack`: mark downloaded items as delivered. They stay downloadable
+/// for `ACK_GRACE_PERIOD_SECONDS`, then the expiry sweeper purges them.
+pub async fn handle_ack(
+    State(state): State<AppState>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<AckRequest>,
+) -> impl IntoResponse {
+    let claims = match verify_jwt_from_header(auth_header.0.token(), &state, "/retrieve").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    if payload.ids.len() > MAX_ACK_IDS {
+        return (StatusCode::BAD_REQUEST, "Too many IDs").into_response();
+    }
+    let ids = match payload
+        .ids
+        .iter()
+        .map(|id| Uuid::parse_str(id))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid UUID format").into_response(),
+    };
+    let now = Utc::now();
+    let purge_at = now + Duration::seconds(state.config.ack_grace_period_seconds);
+    let mailbox = storage_key(&state.config, &claims.sub);
+    let mut acknowledged = Vec::new();
+    for id in ids {
+        match state
+            .storage
+            .acknowledge_item(id, &mailbox, now, purge_at)
+            .await
+        {
+            Ok(true) => acknowledged.push(id),
+            Ok(false) => {}
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("DB error: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    }
+    (
+        StatusCode::OK,
+        Json(AckResponse {
+            acknowledged,
+            purge_at,
+        }),
+    )
+        .into_response()
+}
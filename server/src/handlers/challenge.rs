@@ -1,17 +1,24 @@
-use crate::auth::build_and_encrypt_challenge_jwt;
-use crate::{AppState, config::Config};
-use age::x25519;
+use crate::auth::{build_and_encrypt_challenge_jwt, encrypt_jwt_for_recipient};
+use crate::mailbox::MailboxId;
+use crate::{AppState, capability, config::Config};
 use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
-use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
-use chrono::Utc;
 use serde::Deserialize;
 use serde_json::json;
 
+/// Scopes a challenge JWT can be issued for; the JWT audience is `/<scope>`.
+/// `capability` is special: its JWT is an upload capability rather than an
+/// auth token.
+const SCOPES: &[&str] = &["retrieve", "notify", "delete", "settings", "capability"];
+
 #[derive(Deserialize)]
 pub struct ChallengeRequest {
     pub pubkey: String,
     pub scope: String,
     pub telegram: Option<String>,
+    // Limits of a `capability` scope token
+    pub max_uploads: Option<u32>,
+    pub max_bytes: Option<u64>,
+    pub expires_in: Option<i64>,
 }
 
 pub async fn handle_challenge(
@@ -30,7 +37,7 @@ async fn process_challenge(
     payload: &ChallengeRequest,
 ) -> Result<String, (StatusCode, String)> {
     // Validate scope
-    if payload.scope != "retrieve" && payload.scope != "notify" {
+    if !SCOPES.contains(&payload.scope.as_str()) {
         return Err((StatusCode::BAD_REQUEST, "Invalid scope".to_string()));
     }
     if payload.scope == "notify" && payload.telegram.is_none() {
@@ -39,13 +46,29 @@ async fn process_challenge(
             "Missing telegram for notify scope".to_string(),
         ));
     }
+    // Tokens are issued for the canonical form of the key, whatever the client sent
+    let mailbox = payload
+        .pubkey
+        .parse::<MailboxId>()
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg.to_string()))?;
+    let pubkey = mailbox.as_str();
+    if payload.scope == "capability" {
+        let capability = capability::issue(
+            config,
+            pubkey,
+            payload.max_uploads,
+            payload.max_bytes,
+            payload.expires_in,
+        )?;
+        return encrypt_jwt_for_recipient(&capability, pubkey);
+    }
     let aud = format!("/{}", payload.scope);
     let ciphertext = build_and_encrypt_challenge_jwt(
-        &payload.pubkey,
+        pubkey,
         &aud,
         payload.telegram.as_deref(),
         config,
-        &payload.pubkey,
+        pubkey,
         config.jwt_expiration_seconds,
     )?;
     Ok(ciphertext)
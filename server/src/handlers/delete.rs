@@ -0,0 +1,105 @@
+use crate::AppState;
+use crate::auth::verify_jwt_from_header;
+use crate::blob;
+use crate::error::StorageError;
+use crate::mailbox::storage_key;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const MAX_BULK_DELETE: usize = 100;
+
+#[derive(Deserialize)]
+pub struct BulkDeleteRequest {
+    pub ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BulkDeleteResponse {
+    pub deleted: Vec<Uuid>,
+}
+
+/// Delete an item if it belongs to the mailbox of `sub`. Items of other
+/// mailboxes are treated as missing, like in `handle_download`.
+async fn delete_owned_item(state: &AppState, sub: &str, id: Uuid) -> Result<bool, StorageError> {
+    let owner = storage_key(&state.config, sub);
+    match state.storage.get_item(id).await? {
+        Some(item) if item.pubkey == owner => {
+            blob::delete_item_and_blob(state.storage.as_ref(), state.blobs.as_ref(), &item).await
+        }
+        _ => Ok(false),
+    }
+}
+
+/// `DELETE /items/{item_id}`
+pub async fn handle_delete_item(
+    State(state): State<AppState>,
+    Path(item_id): Path<String>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+    let claims = match verify_jwt_from_header(auth_header.0.token(), &state, "/delete").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    let uuid = match Uuid::parse_str(&item_id) {
+        Ok(id) => id,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid UUID format").into_response(),
+    };
+    match delete_owned_item(&state, &claims.sub, uuid).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Item not found").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// `DELETE /items` with a JSON list of IDs; reports which ones were deleted.
+pub async fn handle_bulk_delete(
+    State(state): State<AppState>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+    Json(payload): Json<BulkDeleteRequest>,
+) -> impl IntoResponse {
+    let claims = match verify_jwt_from_header(auth_header.0.token(), &state, "/delete").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    if payload.ids.len() > MAX_BULK_DELETE {
+        return (StatusCode::BAD_REQUEST, "Too many IDs").into_response();
+    }
+    let ids = match payload
+        .ids
+        .iter()
+        .map(|id| Uuid::parse_str(id))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid UUID format").into_response(),
+    };
+    let mut deleted = Vec::new();
+    for id in ids {
+        match delete_owned_item(&state, &claims.sub, id).await {
+            Ok(true) => deleted.push(id),
+            Ok(false) => {}
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("DB error: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    }
+    (StatusCode::OK, Json(BulkDeleteResponse { deleted })).into_response()
+}
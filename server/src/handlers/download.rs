@@ -1,39 +1,126 @@
-use crate::auth::{AuthClaims, verify_jwt_from_header};
-use crate::db::DbItem;
-use crate::{AppState, db};
-use axum::body::Body;
-use axum::http::Request;
+use crate::auth::verify_jwt_from_header;
+use crate::mailbox::storage_key;
+use crate::{AppState, blob};
 use axum::{
+    body::Body,
     extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode,
+        header::{
+            ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            IF_RANGE, RANGE,
+        },
+    },
+    response::{AppendHeaders, IntoResponse},
 };
 use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
+use futures_util::StreamExt;
+use std::ops::Range;
 use uuid::Uuid;
 
+/// How a request's `Range` header applies to a body of known size.
+#[derive(Debug)]
+enum RangeRequest {
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+/// Interpret a `Range` header for a body of `size` bytes. Only a single
+/// byte range is supported; anything else is served as the full body.
+fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let range = match (start.trim(), end.trim()) {
+        // Suffix range: the last `n` bytes
+        ("", n) => match n.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(n) => size.saturating_sub(n)..size,
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => start..size,
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..size.min(end + 1),
+            _ => return RangeRequest::Full,
+        },
+    };
+    if range.start >= size {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial(range)
+    }
+}
+
+/// Whether an `If-None-Match` or `If-Range` value lists `etag`.
+fn etag_matches(value: &HeaderValue, etag: &str) -> bool {
+    value.to_str().is_ok_and(|value| {
+        value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag)
+    })
+}
+
+/// Deletes a consumed read-once item's blob when the response body is
+/// dropped, i.e. once it has been sent or the client went away.
+struct DeleteBlobOnDrop {
+    state: AppState,
+    blob_key: String,
+}
+
+impl Drop for DeleteBlobOnDrop {
+    fn drop(&mut self) {
+        let state = self.state.clone();
+        let blob_key = std::mem::take(&mut self.blob_key);
+        tokio::spawn(async move {
+            let result = blob::delete_unreferenced_blob(
+                state.storage.as_ref(),
+                state.blobs.as_ref(),
+                &blob_key,
+            )
+            .await;
+            if let Err(e) = result {
+                eprintln!("Failed to delete read-once blob {}: {}", blob_key, e);
+            }
+        });
+    }
+}
+
 pub async fn handle_download(
     State(state): State<AppState>,
     Path(item_id): Path<String>,
+    method: Method,
+    headers: HeaderMap,
     TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
 ) -> impl IntoResponse {
     // Verify JWT and extract claims
     let jwt = auth_header.0.token();
-    let claims = match verify_jwt_from_header(jwt, &state.config, "/retrieve").await {
+    let claims = match verify_jwt_from_header(jwt, &state, "/retrieve").await {
         Ok(c) => c,
         Err((status, msg)) => return (status, msg).into_response(),
     };
     // Check if item_id belongs to the user (claims.sub)
-    let owner_pubkey = &claims.sub;
+    let owner = storage_key(&state.config, &claims.sub);
     let uuid = match Uuid::parse_str(&item_id) {
         Ok(id) => id,
         Err(_) => return (StatusCode::BAD_REQUEST, "Invalid UUID format").into_response(),
     };
-    let item = match DbItem::get_item_by_id(&state.db_pool, uuid).await {
+    let item = match state.storage.get_item(uuid).await {
         Ok(Some(item)) => {
-            if item.pubkey != *owner_pubkey {
+            if item.pubkey != owner {
                 return (StatusCode::NOT_FOUND, "Item not found").into_response();
             }
             item
@@ -41,11 +128,88 @@ pub async fn handle_download(
         Ok(None) => return (StatusCode::NOT_FOUND, "Item not found").into_response(),
         Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "DB error").into_response(),
     };
-    // Return ciphertext as binary
-    (
-        StatusCode::OK,
-        [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
-        item.ciphertext,
-    )
-        .into_response()
+    // Blob keys are the SHA-256 of the ciphertext, so they make a strong ETag
+    let etag = format!("\"{}\"", item.blob_key);
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|value| etag_matches(value, &etag))
+    {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+    let size = item.size as u64;
+    // A stale If-Range validator means the client wants the whole body again.
+    // Read-once items are always sent whole, since they cannot be resumed.
+    let range = match headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+        _ if item.read_once => RangeRequest::Full,
+        Some(_)
+            if headers
+                .get(IF_RANGE)
+                .is_some_and(|v| !etag_matches(v, &etag)) =>
+        {
+            RangeRequest::Full
+        }
+        Some(value) => parse_range(value, size),
+        None => RangeRequest::Full,
+    };
+    let (status, range) = match range {
+        RangeRequest::Full => (StatusCode::OK, None),
+        RangeRequest::Partial(range) => (StatusCode::PARTIAL_CONTENT, Some(range)),
+        RangeRequest::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response();
+        }
+    };
+    let mut response_headers = vec![
+        (CONTENT_TYPE, "application/octet-stream".to_string()),
+        (ACCEPT_RANGES, "bytes".to_string()),
+        (ETAG, etag),
+    ];
+    if let Some(range) = &range {
+        response_headers.push((
+            CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+        ));
+    }
+    // HEAD only needs the metadata; skip opening (or consuming) the blob
+    if method == Method::HEAD {
+        let length = range.map_or(size, |range| range.end - range.start);
+        response_headers.push((CONTENT_LENGTH, length.to_string()));
+        return (status, AppendHeaders(response_headers)).into_response();
+    }
+    let blob = match range {
+        Some(range) => state.blobs.open_range(&item.blob_key, range).await,
+        None => state.blobs.open(&item.blob_key).await,
+    };
+    let blob = match blob {
+        Ok(Some(blob)) => blob,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Item not found").into_response(),
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Blob store error").into_response(),
+    };
+    // Only one concurrent download of a read-once item wins the atomic take.
+    // The blob is opened first so a blob store failure leaves the item in place.
+    if item.read_once {
+        match state.storage.take_read_once_item(uuid, &owner).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return (StatusCode::NOT_FOUND, "Item not found").into_response(),
+            Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "DB error").into_response(),
+        }
+    }
+    // Stream ciphertext as binary
+    response_headers.push((CONTENT_LENGTH, blob.size.to_string()));
+    let body = if item.read_once {
+        let guard = DeleteBlobOnDrop {
+            state: state.clone(),
+            blob_key: item.blob_key.clone(),
+        };
+        Body::from_stream(blob.stream.map(move |chunk| {
+            let _ = &guard;
+            chunk
+        }))
+    } else {
+        Body::from_stream(blob.stream)
+    };
+    (status, AppendHeaders(response_headers), body).into_response()
 }
@@ -0,0 +1,8 @@
+use crate::AppState;
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+
+/// `GET /.well-known/jwks.json`: public keys of the EdDSA signing keys, so
+/// other services can verify bearer tokens. Empty with HS256 only.
+pub async fn handle_jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (StatusCode::OK, Json(state.config.keyring.jwks()))
+}
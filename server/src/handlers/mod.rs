@@ -1,5 +1,12 @@
+pub mod ack;
 pub mod challenge;
+pub mod delete;
 pub mod download;
+pub mod jwks;
 pub mod notify;
+pub mod pow;
 pub mod retrieve;
+pub mod settings;
 pub mod upload;
+pub mod uploads;
+pub mod usage;
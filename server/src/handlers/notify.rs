@@ -1,6 +1,34 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use crate::mailbox::storage_key;
+use crate::{AppState, auth::verify_jwt_from_header};
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
 
-pub async fn handle_notify() -> impl IntoResponse {
-    // Call the actual implementation here (to be implemented in another module)
-    (StatusCode::OK, "notify handler stub")
+pub async fn handle_notify(
+    State(state): State<AppState>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+    // Verify JWT and extract claims
+    let jwt = auth_header.0.token();
+    let claims = match verify_jwt_from_header(jwt, &state, "/notify").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    // The Telegram target is carried in the token issued by /challenge
+    let telegram = match claims.telegram.as_deref() {
+        Some(t) if !t.is_empty() => t,
+        _ => return (StatusCode::UNAUTHORIZED, "Missing telegram claim").into_response(),
+    };
+    // Store registration in DB
+    let mailbox = storage_key(&state.config, &claims.sub);
+    match state.storage.upsert_notification(&mailbox, telegram).await {
+        Ok(_registration) => (StatusCode::OK, "ok").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response(),
+    }
 }
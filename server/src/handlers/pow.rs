@@ -0,0 +1,55 @@
+use crate::handlers::upload::parse_pubkey;
+use crate::{AppState, pow};
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct PowChallengeResponse {
+    pub challenge: String,
+    pub difficulty: u32, // leading zero bits required; 0 means no PoW is needed
+    pub expires_at: DateTime<Utc>,
+}
+
+/// `POST /pow`: issue a proof-of-work challenge for uploads to `X-PubKey`.
+pub async fn handle_pow_challenge(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let mailbox = match parse_pubkey(&headers) {
+        Ok(mailbox) => mailbox,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let pubkey = mailbox.as_str();
+    let difficulty = match pow::required_difficulty(&state, pubkey).await {
+        Ok(difficulty) => difficulty,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("DB error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    match pow::issue(&state.config, pubkey, difficulty) {
+        Ok((challenge, expires_at)) => (
+            StatusCode::OK,
+            Json(PowChallengeResponse {
+                challenge,
+                difficulty,
+                expires_at,
+            }),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("JWT error: {}", e),
+        )
+            .into_response(),
+    }
+}
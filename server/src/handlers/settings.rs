@@ -0,0 +1,67 @@
+use crate::AppState;
+use crate::auth::verify_jwt_from_header;
+use crate::db::MailboxSettings;
+use crate::mailbox::storage_key;
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+
+/// `GET /settings`: the caller's mailbox settings.
+pub async fn handle_get_settings(
+    State(state): State<AppState>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+    let claims = match verify_jwt_from_header(auth_header.0.token(), &state, "/settings").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    let mailbox = storage_key(&state.config, &claims.sub);
+    match state.storage.get_mailbox_settings(&mailbox).await {
+        Ok(settings) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// `PUT /settings`: replace the caller's mailbox settings.
+pub async fn handle_put_settings(
+    State(state): State<AppState>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+    Json(settings): Json<MailboxSettings>,
+) -> impl IntoResponse {
+    let claims = match verify_jwt_from_header(auth_header.0.token(), &state, "/settings").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    if settings
+        .pow_difficulty
+        .is_some_and(|d| d < 0 || d as u32 > state.config.pow_max_difficulty)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "pow_difficulty must be between 0 and {}",
+                state.config.pow_max_difficulty
+            ),
+        )
+            .into_response();
+    }
+    let mailbox = storage_key(&state.config, &claims.sub);
+    match state
+        .storage
+        .set_mailbox_settings(&mailbox, &settings)
+        .await
+    {
+        Ok(()) => (StatusCode::OK, Json(settings)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response(),
+    }
+}
@@ -1,39 +1,426 @@
-use crate::{AppState, db::DbItem};
-use age::x25519;
-use axum::{body::Bytes, extract::State, http::StatusCode, response::IntoResponse};
+use crate::agefile::{self, AgeFormat};
+use crate::blob::{self, StagedBlob};
+use crate::capability::{self, CapabilityClaims};
+use crate::config::Config;
+use crate::error::{BlobError, StorageError};
+use crate::mailbox::{MailboxId, storage_key};
+use crate::storage::{Quota, Usage};
+use crate::{AppState, db::NewItem, pow, retention, telegram};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, StatusCode, header::CONTENT_LENGTH},
+    response::{AppendHeaders, IntoResponse, Response},
+};
+
+const MAX_LABELS: usize = 8;
+const MAX_LABEL_LEN: usize = 64;
+
+#[cfg(not(feature = "pq"))]
+const INVALID_PUBKEY: &str = "X-PubKey must be a valid age X25519 or SSH pubkey";
+#[cfg(feature = "pq")]
+const INVALID_PUBKEY: &str =
+    "X-PubKey must be a valid age X25519, ML-KEM-768 + X25519 (age1pq1) or SSH pubkey";
+
+/// Extract the recipient mailbox from the `X-PubKey` header: an `age1...` or
+/// SSH key (or with the `pq` feature an `age1pq1...` key), or its base64
+/// encoding.
+pub(crate) fn parse_pubkey(headers: &HeaderMap) -> Result<MailboxId, &'static str> {
+    headers
+        .get("X-PubKey")
+        .ok_or("Missing X-PubKey header")?
+        .to_str()
+        .map_err(|_| "Invalid X-PubKey header")?
+        .parse()
+        .map_err(|_| INVALID_PUBKEY)
+}
+
+/// Body length announced by the client, if any.
+pub(crate) fn declared_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Largest body a sender may upload: `MAX_UPLOAD_BYTES`, lowered to the
+/// mailbox byte quota since no single item can exceed that.
+pub(crate) fn upload_limit(config: &Config) -> u64 {
+    match config.mailbox_max_bytes {
+        Some(quota) => config.max_upload_bytes.min(quota.max(0) as u64),
+        None => config.max_upload_bytes,
+    }
+}
+
+/// `X-Quota-Remaining-*` headers for the limits that are configured.
+pub(crate) fn quota_headers(quota: Quota, usage: Usage) -> Vec<(&'static str, String)> {
+    let mut headers = Vec::new();
+    if let Some(max) = quota.max_items {
+        headers.push((
+            "X-Quota-Remaining-Items",
+            (max - usage.items).max(0).to_string(),
+        ));
+    }
+    if let Some(max) = quota.max_bytes {
+        headers.push((
+            "X-Quota-Remaining-Bytes",
+            (max - usage.bytes).max(0).to_string(),
+        ));
+    }
+    headers
+}
+
+fn quota_exceeded(quota: Quota, usage: Usage) -> Response {
+    (
+        StatusCode::INSUFFICIENT_STORAGE,
+        AppendHeaders(quota_headers(quota, usage)),
+        "Mailbox quota exceeded",
+    )
+        .into_response()
+}
+
+/// Reject an upload of `size` bytes with 507 before receiving its body when
+/// the mailbox stored under `mailbox` is already too full for it.
+pub(crate) async fn check_mailbox_space(
+    state: &AppState,
+    mailbox: &str,
+    size: u64,
+) -> Result<(), Response> {
+    let quota = Quota::from_config(&state.config);
+    if quota.is_unlimited() {
+        return Ok(());
+    }
+    match state.storage.mailbox_usage(mailbox).await {
+        Ok(usage) if quota.allows(usage, size as i64) => Ok(()),
+        Ok(usage) => Err(quota_exceeded(quota, usage)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// Check the `X-Capability` token of an upload, if any. Mailboxes in
+/// capability-required mode reject uploads without one. The use is only
+/// counted by [`store_item`], once the body has been accepted.
+pub(crate) async fn check_capability(
+    state: &AppState,
+    headers: &HeaderMap,
+    pubkey: &str,
+) -> Result<Option<CapabilityClaims>, Response> {
+    let db_error = |e: StorageError| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response()
+    };
+    let token = match headers.get(capability::HEADER).map(|v| v.to_str()) {
+        None => {
+            let settings = state
+                .storage
+                .get_mailbox_settings(&storage_key(&state.config, pubkey))
+                .await
+                .map_err(db_error)?;
+            if settings.capability_required {
+                return Err((StatusCode::FORBIDDEN, "Upload capability required").into_response());
+            }
+            return Ok(None);
+        }
+        Some(Err(_)) => {
+            return Err((StatusCode::FORBIDDEN, "Malformed X-Capability header").into_response());
+        }
+        Some(Ok(token)) => token,
+    };
+    let claims = capability::verify(&state.config, pubkey, token)
+        .map_err(|msg| (StatusCode::FORBIDDEN, msg).into_response())?;
+    Ok(Some(claims))
+}
+
+/// Require a valid, unspent `X-PoW` stamp when the deployment or the mailbox
+/// asks for proof of work. Failures answer 403 with the required
+/// `X-PoW-Difficulty`.
+pub(crate) async fn check_pow(
+    state: &AppState,
+    headers: &HeaderMap,
+    pubkey: &str,
+) -> Result<(), Response> {
+    let required = match pow::required_difficulty(state, pubkey).await {
+        Ok(0) => return Ok(()),
+        Ok(required) => required,
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("DB error: {}", e),
+            )
+                .into_response());
+        }
+    };
+    let forbidden = |msg: &'static str| {
+        (
+            StatusCode::FORBIDDEN,
+            [("X-PoW-Difficulty", required.to_string())],
+            msg,
+        )
+            .into_response()
+    };
+    let (nonce, expires_at) = match headers.get(pow::HEADER).map(|v| v.to_str()) {
+        None => return Err(forbidden("Proof of work required")),
+        Some(Err(_)) => return Err(forbidden("Malformed X-PoW header")),
+        Some(Ok(stamp)) => {
+            pow::verify(&state.config, pubkey, stamp, required).map_err(forbidden)?
+        }
+    };
+    match state.seen_tokens.first_use(nonce, expires_at).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(forbidden("PoW stamp was already used")),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Replay store error: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// With `VALIDATE_AGE_UPLOADS`, require a staged body to be an age file with a
+/// stanza of the mailbox's recipient type, and return its encoding.
+pub(crate) async fn check_age_file(
+    state: &AppState,
+    mailbox: &MailboxId,
+    staged: &StagedBlob,
+) -> Result<Option<AgeFormat>, Response> {
+    if !state.config.validate_age_uploads {
+        return Ok(None);
+    }
+    let prefix = agefile::read_prefix(staged.path()).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Upload error: {}", e),
+        )
+            .into_response()
+    })?;
+    let header = agefile::parse_header(&prefix)
+        .map_err(|msg| (StatusCode::BAD_REQUEST, msg).into_response())?;
+    let expected = mailbox.stanza_type();
+    if !header.stanza_types.iter().any(|t| t == expected) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Body is not encrypted to an age {} recipient", expected),
+        )
+            .into_response());
+    }
+    Ok(Some(header.format))
+}
+
+/// Release the blob just stored for an upload that was then rejected.
+async fn release_rejected_blob(state: &AppState, blob_key: &str) {
+    if let Err(e) =
+        blob::delete_unreferenced_blob(state.storage.as_ref(), state.blobs.as_ref(), blob_key).await
+    {
+        eprintln!("Failed to delete rejected blob {}: {}", blob_key, e);
+    }
+}
+
+/// Insert an uploaded item within the mailbox quota and notify the recipient.
+/// The upload's capability, if any, is counted here and given back if the
+/// item cannot be stored. If the capability is used up or the mailbox is
+/// full, the blob just stored for it is released again.
+pub(crate) async fn store_item(
+    state: &AppState,
+    new_item: &NewItem,
+    capability: Option<&CapabilityClaims>,
+) -> Response {
+    if let Some(claims) = capability {
+        match capability::consume(state, claims).await {
+            Ok(true) => {}
+            Ok(false) => {
+                release_rejected_blob(state, &new_item.blob_key).await;
+                return (StatusCode::FORBIDDEN, "Upload capability is used up").into_response();
+            }
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("DB error: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    }
+    let quota = Quota::from_config(&state.config);
+    let result = state.storage.insert_item(new_item, quota).await;
+    if result.is_err()
+        && let Some(claims) = capability
+        && let Err(e) = capability::release(state, claims).await
+    {
+        eprintln!("Failed to release capability {}: {}", claims.jti, e);
+    }
+    if let Err(StorageError::QuotaExceeded) = result {
+        release_rejected_blob(state, &new_item.blob_key).await;
+    }
+    let item = match result {
+        Ok(item) => item,
+        Err(StorageError::QuotaExceeded) => {
+            let usage = state
+                .storage
+                .mailbox_usage(&new_item.pubkey)
+                .await
+                .unwrap_or_default();
+            return quota_exceeded(quota, usage);
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("DB error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    telegram::dispatch_new_item(state, &new_item.pubkey, item.id);
+    if quota.is_unlimited() {
+        return (StatusCode::CREATED, "ok").into_response();
+    }
+    let usage = state
+        .storage
+        .mailbox_usage(&new_item.pubkey)
+        .await
+        .unwrap_or_default();
+    (
+        StatusCode::CREATED,
+        AppendHeaders(quota_headers(quota, usage)),
+        "ok",
+    )
+        .into_response()
+}
+
+/// Parse the optional `X-Expires-In` header: a positive TTL in seconds.
+pub(crate) fn parse_expires_in(headers: &HeaderMap) -> Result<Option<i64>, &'static str> {
+    let Some(value) = headers.get("X-Expires-In") else {
+        return Ok(None);
+    };
+    match value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse::<u32>().ok())
+    {
+        Some(seconds) if seconds > 0 => Ok(Some(seconds as i64)),
+        _ => Err("X-Expires-In must be a positive number of seconds"),
+    }
+}
+
+/// Parse the optional `X-Read-Once` header (`true`/`false`, `1`/`0`).
+pub(crate) fn parse_read_once(headers: &HeaderMap) -> Result<bool, &'static str> {
+    match headers.get("X-Read-Once").map(|v| v.to_str()) {
+        None => Ok(false),
+        Some(Ok("true" | "1")) => Ok(true),
+        Some(Ok("false" | "0")) => Ok(false),
+        Some(_) => Err("X-Read-Once must be true or false"),
+    }
+}
+
+/// Parse the optional comma-separated `X-Labels` header.
+pub(crate) fn parse_labels(headers: &HeaderMap) -> Result<Option<Vec<String>>, &'static str> {
+    let Some(value) = headers.get("X-Labels") else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| "Invalid X-Labels header")?;
+    let labels: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(str::to_string)
+        .collect();
+    if labels.len() > MAX_LABELS || labels.iter().any(|label| label.len() > MAX_LABEL_LEN) {
+        return Err("Too many or too long labels in X-Labels");
+    }
+    Ok((!labels.is_empty()).then_some(labels))
+}
 
 pub async fn handle_upload(
     State(state): State<AppState>,
-    headers: axum::http::HeaderMap,
-    body: Bytes,
+    headers: HeaderMap,
+    body: Body,
 ) -> impl IntoResponse {
-    // Extract X-PubKey header
-    let pubkey_b64 = match headers.get("X-PubKey") {
-        Some(val) => match val.to_str() {
-            Ok(s) => s,
-            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid X-PubKey header").into_response(),
-        },
-        None => return (StatusCode::BAD_REQUEST, "Missing X-PubKey header").into_response(),
-    };
-    // Validate pubkey (must be valid age X25519 pubkey)
-    if pubkey_b64.parse::<x25519::Recipient>().is_err() {
-        return (
-            StatusCode::BAD_REQUEST,
-            "X-PubKey must be a valid age X25519 pubkey",
-        )
-            .into_response();
+    let mailbox = match parse_pubkey(&headers) {
+        Ok(mailbox) => mailbox,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let pubkey = mailbox.as_str();
+    let mailbox_key = storage_key(&state.config, pubkey);
+    let labels = match parse_labels(&headers) {
+        Ok(labels) => labels,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let expires_in = match parse_expires_in(&headers) {
+        Ok(expires_in) => expires_in,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let read_once = match parse_read_once(&headers) {
+        Ok(read_once) => read_once,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    // A capability from the recipient stands in for proof of work
+    let capability = match check_capability(&state, &headers, pubkey).await {
+        Ok(capability) => capability,
+        Err(response) => return response,
+    };
+    if capability.is_none()
+        && let Err(response) = check_pow(&state, &headers, pubkey).await
+    {
+        return response;
+    }
+    // Reject oversized bodies and full mailboxes up front when possible
+    let max_bytes = match capability.as_ref().and_then(|claims| claims.max_bytes) {
+        Some(max) => upload_limit(&state.config).min(max),
+        None => upload_limit(&state.config),
+    };
+    let declared = declared_length(&headers);
+    if declared.is_some_and(|len| len > max_bytes) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Body too large").into_response();
     }
+    if let Err(response) = check_mailbox_space(&state, &mailbox_key, declared.unwrap_or(0)).await {
+        return response;
+    }
+    // Spool the body to disk while hashing it, so memory use stays flat
+    let staging_dir = blob::staging_dir(&state.config);
+    let staged =
+        match StagedBlob::from_stream(&staging_dir, body.into_data_stream(), max_bytes).await {
+            Ok(staged) => staged,
+            Err(BlobError::TooLarge) => {
+                return (StatusCode::PAYLOAD_TOO_LARGE, "Body too large").into_response();
+            }
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Upload error: {}", e),
+                )
+                    .into_response();
+            }
+        };
     // Validate body (must not be empty)
-    if body.is_empty() {
+    if staged.size() == 0 {
         return (StatusCode::BAD_REQUEST, "Empty body").into_response();
     }
-    // Store in DB
-    match DbItem::insert(&state.db_pool, pubkey_b64, &body).await {
-        Ok(_item) => (StatusCode::CREATED, "ok").into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("DB error: {}", e),
-        )
-            .into_response(),
-    }
+    let format = match check_age_file(&state, &mailbox, &staged).await {
+        Ok(format) => format,
+        Err(response) => return response,
+    };
+    let size = staged.size() as i64;
+    // Store ciphertext in the blob store, then its metadata in the DB
+    let blob_key = match state.blobs.put_staged(staged).await {
+        Ok(key) => key,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Blob store error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    let mut new_item = NewItem::new(&mailbox_key, &blob_key, size);
+    new_item.labels = labels;
+    new_item.expires_at = retention::expires_at(&state.config, expires_in);
+    new_item.read_once = read_once;
+    new_item.format = format;
+    store_item(&state, &new_item, capability.as_ref()).await
 }
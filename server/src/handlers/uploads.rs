@@ -0,0 +1,328 @@
+use crate::blob::StagedBlob;
+use crate::db::{DbUploadSession, NewItem, NewUploadSession};
+use crate::error::BlobError;
+use crate::handlers::upload::{
+    check_age_file, check_capability, check_mailbox_space, check_pow, declared_length,
+    parse_expires_in, parse_labels, parse_pubkey, parse_read_once, store_item, upload_limit,
+};
+use crate::mailbox::{MailboxId, storage_key};
+use crate::uploads::SessionLock;
+use crate::{AppState, retention, uploads};
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header::LOCATION},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::io::ErrorKind;
+use uuid::Uuid;
+
+const OFFSET_HEADER: &str = "X-Upload-Offset";
+
+#[derive(Serialize)]
+pub struct UploadProgress {
+    pub upload_id: Uuid,
+    pub offset: i64, // bytes received and acknowledged so far
+}
+
+impl UploadProgress {
+    fn of(session: &DbUploadSession) -> Self {
+        UploadProgress {
+            upload_id: session.id,
+            offset: session.received,
+        }
+    }
+}
+
+/// Look up a session and its mailbox, answering 404 unless it belongs to the
+/// `X-PubKey` mailbox.
+async fn owned_session(
+    state: &AppState,
+    headers: &HeaderMap,
+    upload_id: &str,
+) -> Result<(MailboxId, DbUploadSession), Response> {
+    let mailbox =
+        parse_pubkey(headers).map_err(|msg| (StatusCode::BAD_REQUEST, msg).into_response())?;
+    let mailbox_key = storage_key(&state.config, mailbox.as_str());
+    let id = Uuid::parse_str(upload_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid UUID format").into_response())?;
+    match state.storage.get_upload_session(id).await {
+        Ok(Some(session)) if session.pubkey == mailbox_key => Ok((mailbox, session)),
+        Ok(_) => Err((StatusCode::NOT_FOUND, "Upload not found").into_response()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// [`owned_session`], claimed with [`uploads::try_lock_session`] and read
+/// again once claimed so that its offset is current. Answers 409 while
+/// another request writes or finalizes the session.
+async fn locked_session(
+    state: &AppState,
+    headers: &HeaderMap,
+    upload_id: &str,
+) -> Result<(MailboxId, DbUploadSession, SessionLock), Response> {
+    let (mailbox, session) = owned_session(state, headers, upload_id).await?;
+    let not_found = || (StatusCode::NOT_FOUND, "Upload not found").into_response();
+    let lock = match uploads::try_lock_session(&state.config, session.id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            return Err(
+                (StatusCode::CONFLICT, "Upload is busy with another request").into_response(),
+            );
+        }
+        // Finalized in the meantime
+        Err(BlobError::Io(e)) if e.kind() == ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Upload error: {}", e),
+            )
+                .into_response());
+        }
+    };
+    match state.storage.get_upload_session(session.id).await {
+        Ok(Some(session)) => Ok((mailbox, session, lock)),
+        Ok(None) => Err(not_found()),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response()),
+    }
+}
+
+/// `POST /uploads`: start a resumable upload for the `X-PubKey` mailbox.
+pub async fn handle_create_upload(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let mailbox = match parse_pubkey(&headers) {
+        Ok(mailbox) => mailbox,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let pubkey = mailbox.as_str();
+    let mailbox_key = storage_key(&state.config, pubkey);
+    let labels = match parse_labels(&headers) {
+        Ok(labels) => labels,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let expires_in = match parse_expires_in(&headers) {
+        Ok(expires_in) => expires_in,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let read_once = match parse_read_once(&headers) {
+        Ok(read_once) => read_once,
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let capability = match check_capability(&state, &headers, pubkey).await {
+        Ok(capability) => capability,
+        Err(response) => return response,
+    };
+    if capability.is_none()
+        && let Err(response) = check_pow(&state, &headers, pubkey).await
+    {
+        return response;
+    }
+    if let Err(response) = check_mailbox_space(&state, &mailbox_key, 0).await {
+        return response;
+    }
+    let mut new_session = NewUploadSession::new(&mailbox_key);
+    new_session.labels = labels;
+    new_session.expires_in = expires_in;
+    new_session.read_once = read_once;
+    new_session.max_size = capability
+        .as_ref()
+        .and_then(|claims| claims.max_bytes)
+        .map(|max| max as i64);
+    new_session.capability = capability;
+    let session = match state.storage.create_upload_session(&new_session).await {
+        Ok(session) => session,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("DB error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    if let Err(e) = uploads::create_session_file(&state.config, session.id).await {
+        let _ = state.storage.delete_upload_session(session.id).await;
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Upload error: {}", e),
+        )
+            .into_response();
+    }
+    (
+        StatusCode::CREATED,
+        [(LOCATION, format!("/uploads/{}", session.id))],
+        Json(UploadProgress::of(&session)),
+    )
+        .into_response()
+}
+
+/// `GET`/`HEAD /uploads/{upload_id}`: report how many bytes were received.
+pub async fn handle_upload_progress(
+    State(state): State<AppState>,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    match owned_session(&state, &headers, &upload_id).await {
+        Ok((_, session)) => (
+            StatusCode::OK,
+            [(OFFSET_HEADER, session.received.to_string())],
+            Json(UploadProgress::of(&session)),
+        )
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+/// `PUT /uploads/{upload_id}`: append the body at `X-Upload-Offset`, which
+/// must equal the number of bytes received so far.
+pub async fn handle_upload_chunk(
+    State(state): State<AppState>,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> impl IntoResponse {
+    let (_, session, _lock) = match locked_session(&state, &headers, &upload_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    let offset = match headers
+        .get(OFFSET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok())
+    {
+        Some(offset) => offset,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Missing or invalid X-Upload-Offset header",
+            )
+                .into_response();
+        }
+    };
+    // Tell the client where to resume instead of accepting a gap or overlap
+    if offset != session.received {
+        return (
+            StatusCode::CONFLICT,
+            [(OFFSET_HEADER, session.received.to_string())],
+            "Offset does not match received bytes",
+        )
+            .into_response();
+    }
+    let max_bytes = match session.max_size {
+        Some(max) => upload_limit(&state.config).min(max as u64),
+        None => upload_limit(&state.config),
+    };
+    if declared_length(&headers).is_some_and(|len| offset as u64 + len > max_bytes) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Body too large").into_response();
+    }
+    let path = uploads::session_path(&state.config, session.id);
+    let written = match uploads::write_chunk(
+        &path,
+        offset as u64,
+        body.into_data_stream(),
+        max_bytes,
+    )
+    .await
+    {
+        Ok(written) => written as i64,
+        Err(BlobError::TooLarge) => {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Body too large").into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Upload error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    match state
+        .storage
+        .advance_upload_session(session.id, offset, offset + written)
+        .await
+    {
+        Ok(true) => (
+            StatusCode::NO_CONTENT,
+            [(OFFSET_HEADER, (offset + written).to_string())],
+        )
+            .into_response(),
+        Ok(false) => (StatusCode::CONFLICT, "Upload changed concurrently").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("DB error: {}", e),
+        )
+            .into_response(),
+    }
+}
+
+/// `POST /uploads/{upload_id}/finalize`: turn the received bytes into an item.
+pub async fn handle_finalize_upload(
+    State(state): State<AppState>,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let (mailbox, session, _lock) = match locked_session(&state, &headers, &upload_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if session.received == 0 {
+        return (StatusCode::BAD_REQUEST, "Empty body").into_response();
+    }
+    // Deleting the session claims it, so concurrent finalizes create one item
+    match state.storage.delete_upload_session(session.id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "Upload not found").into_response(),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("DB error: {}", e),
+            )
+                .into_response();
+        }
+    }
+    let path = uploads::session_path(&state.config, session.id);
+    let staged = match StagedBlob::from_file(path, session.received as u64).await {
+        Ok(staged) => staged,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Upload error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    // A rejected body is discarded with its session
+    let format = match check_age_file(&state, &mailbox, &staged).await {
+        Ok(format) => format,
+        Err(response) => return response,
+    };
+    let blob_key = match state.blobs.put_staged(staged).await {
+        Ok(key) => key,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Blob store error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    let mut new_item = NewItem::new(&session.pubkey, &blob_key, session.received);
+    new_item.labels = session.labels.map(|labels| labels.0);
+    // The TTL counts from when the item becomes visible, not from session start
+    new_item.expires_at = retention::expires_at(&state.config, session.expires_in);
+    new_item.read_once = session.read_once;
+    new_item.format = format;
+    let capability = session.capability.map(|claims| claims.0);
+    store_item(&state, &new_item, capability.as_ref()).await
+}
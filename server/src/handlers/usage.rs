@@ -0,0 +1,52 @@
+use crate::AppState;
+use crate::auth::verify_jwt_from_header;
+use crate::mailbox::storage_key;
+use crate::storage::{Quota, Usage};
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use axum_extra::{
+    TypedHeader,
+    headers::{Authorization, authorization::Bearer},
+};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct UsageResponse {
+    #[serde(flatten)]
+    pub usage: Usage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<i64>,
+}
+
+/// `GET /usage`: the mailbox's current usage and quota, for its owner.
+pub async fn handle_usage(
+    State(state): State<AppState>,
+    TypedHeader(auth_header): TypedHeader<Authorization<Bearer>>,
+) -> impl IntoResponse {
+    let claims = match verify_jwt_from_header(auth_header.0.token(), &state, "/retrieve").await {
+        Ok(c) => c,
+        Err((status, msg)) => return (status, msg).into_response(),
+    };
+    let mailbox = storage_key(&state.config, &claims.sub);
+    let usage = match state.storage.mailbox_usage(&mailbox).await {
+        Ok(usage) => usage,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("DB error: {}", e),
+            )
+                .into_response();
+        }
+    };
+    let quota = Quota::from_config(&state.config);
+    (
+        StatusCode::OK,
+        Json(UsageResponse {
+            usage,
+            max_items: quota.max_items,
+            max_bytes: quota.max_bytes,
+        }),
+    )
+        .into_response()
+}
@@ -0,0 +1,259 @@
+//! Signing keys for every JWT the server issues (challenge tokens, PoW
+//! challenges, upload capabilities and `/retrieve` cursors).
+//!
+//! Tokens carry the `kid` of the key that signed them. One key is active and
+//! signs new tokens; the others only verify, so a secret can be rotated
+//! without invalidating tokens and cursors already handed out. Keys come from
+//! `JWT_KEYS_FILE` and/or `JWT_SECRET`, which is the key `default`.
+//!
+//! Keys are HS256 secrets or Ed25519 (EdDSA) private keys. The public half of
+//! Ed25519 keys is published as a JWKS so that other services can verify
+//! tokens without being able to mint them.
+
+use crate::config::Config;
+use crate::error::KeyringError;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use jsonwebtoken::errors::{Error, ErrorKind};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, decode_header,
+    encode,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+
+/// `kid` of the key built from `JWT_SECRET`; also used for tokens without a `kid`.
+pub const DEFAULT_KID: &str = "default";
+
+/// Contents of `JWT_KEYS_FILE`.
+#[derive(Deserialize)]
+struct KeysFile {
+    active: String,
+    keys: Vec<KeyEntry>,
+}
+
+/// One key of the keys file: an HS256 `secret` or the path of an Ed25519
+/// private key in PKCS#8 PEM format.
+#[derive(Deserialize)]
+struct KeyEntry {
+    kid: String,
+    secret: Option<String>,
+    private_key_file: Option<String>,
+}
+
+#[derive(Clone)]
+struct Key {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    public_key: Option<String>, // base64url Ed25519 public key, for the JWKS
+}
+
+impl Key {
+    fn hmac(secret: &str) -> Self {
+        Key {
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            public_key: None,
+        }
+    }
+
+    fn ed25519(pem: &str) -> Result<Self, KeyringError> {
+        let invalid = |e: &dyn fmt::Display| KeyringError::Invalid(format!("Ed25519 key: {}", e));
+        let signing_key =
+            ed25519_dalek::SigningKey::from_pkcs8_pem(pem).map_err(|e| invalid(&e))?;
+        let public_key = URL_SAFE_NO_PAD.encode(signing_key.verifying_key().as_bytes());
+        Ok(Key {
+            algorithm: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_pem(pem.as_bytes()).map_err(|e| invalid(&e))?,
+            decoding: DecodingKey::from_ed_components(&public_key).map_err(|e| invalid(&e))?,
+            public_key: Some(public_key),
+        })
+    }
+
+    fn ed25519_file(path: &str) -> Result<Self, KeyringError> {
+        Self::ed25519(&std::fs::read_to_string(path)?)
+    }
+
+    /// JWK thumbprint (RFC 7638), used as the `kid` of a lone Ed25519 key.
+    fn thumbprint(&self) -> Option<String> {
+        let x = self.public_key.as_ref()?;
+        let canonical = format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x);
+        Some(URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes())))
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Keyring {
+    active: String,
+    keys: HashMap<String, Key>,
+}
+
+impl fmt::Debug for Keyring {
+    // Never print key material along with the config
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kids: Vec<&String> = self.keys.keys().collect();
+        kids.sort();
+        f.debug_struct("Keyring")
+            .field("active", &self.active)
+            .field("kids", &kids)
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// A keyring holding only `secret` as the active `default` key.
+    pub fn from_secret(secret: &str) -> Self {
+        Keyring {
+            active: DEFAULT_KID.to_string(),
+            keys: HashMap::from([(DEFAULT_KID.to_string(), Key::hmac(secret))]),
+        }
+    }
+
+    /// Build the keyring from the config. `JWT_KEYS_FILE` takes precedence;
+    /// otherwise `JWT_ALGORITHM` selects `JWT_SECRET` (`HS256`, the default)
+    /// or `JWT_ED25519_KEY_FILE` (`EdDSA`) as the only signing key. Unless it
+    /// signs, `JWT_SECRET` stays valid for verification as the `default` key,
+    /// so switching keys does not invalidate tokens already issued.
+    pub fn from_config(config: &Config) -> Result<Self, KeyringError> {
+        let mut keyring = if let Some(path) = &config.jwt_keys_file {
+            Self::parse(&std::fs::read_to_string(path)?)?
+        } else {
+            match config.jwt_algorithm.as_str() {
+                "HS256" => {
+                    let secret = config.jwt_secret.as_deref().ok_or_else(|| {
+                        KeyringError::Invalid("JWT_SECRET or JWT_KEYS_FILE must be set".to_string())
+                    })?;
+                    return Ok(Self::from_secret(secret));
+                }
+                "EdDSA" => {
+                    let path = config.jwt_ed25519_key_file.as_deref().ok_or_else(|| {
+                        KeyringError::Invalid(
+                            "JWT_ED25519_KEY_FILE must be set for EdDSA".to_string(),
+                        )
+                    })?;
+                    let key = Key::ed25519_file(path)?;
+                    let kid = key.thumbprint().unwrap_or_default();
+                    Keyring {
+                        active: kid.clone(),
+                        keys: HashMap::from([(kid, key)]),
+                    }
+                }
+                other => {
+                    return Err(KeyringError::Invalid(format!(
+                        "Unsupported JWT_ALGORITHM {}",
+                        other
+                    )));
+                }
+            }
+        };
+        if let Some(secret) = &config.jwt_secret {
+            keyring
+                .keys
+                .entry(DEFAULT_KID.to_string())
+                .or_insert_with(|| Key::hmac(secret));
+        }
+        Ok(keyring)
+    }
+
+    /// Parse the JSON keys file format:
+    /// `{"active": "<kid>", "keys": [{"kid": "<kid>", "secret": "<secret>"},
+    /// {"kid": "<kid>", "private_key_file": "<Ed25519 PKCS#8 PEM path>"}, ...]}`.
+    pub fn parse(contents: &str) -> Result<Self, KeyringError> {
+        let file: KeysFile = serde_json::from_str(contents)
+            .map_err(|e| KeyringError::Invalid(format!("Invalid keys file: {}", e)))?;
+        let mut keys = HashMap::new();
+        for entry in file.keys {
+            let key = match (&entry.secret, &entry.private_key_file) {
+                (Some(secret), None) if !secret.is_empty() => Key::hmac(secret),
+                (None, Some(path)) => Key::ed25519_file(path)?,
+                _ => {
+                    return Err(KeyringError::Invalid(format!(
+                        "Key {} needs either a secret or a private_key_file",
+                        entry.kid
+                    )));
+                }
+            };
+            if keys.insert(entry.kid.clone(), key).is_some() {
+                return Err(KeyringError::Invalid(format!(
+                    "Duplicate kid {}",
+                    entry.kid
+                )));
+            }
+        }
+        if !keys.contains_key(&file.active) {
+            return Err(KeyringError::Invalid(format!(
+                "Active kid {} is not in the keys file",
+                file.active
+            )));
+        }
+        Ok(Keyring {
+            active: file.active,
+            keys,
+        })
+    }
+
+    /// `kid` of the key signing new tokens.
+    pub fn active_kid(&self) -> &str {
+        &self.active
+    }
+
+    /// Public keys of the Ed25519 keys as a JWK Set. HS256 secrets are never
+    /// included.
+    pub fn jwks(&self) -> Value {
+        let mut keys: Vec<Value> = self
+            .keys
+            .iter()
+            .filter_map(|(kid, key)| {
+                let x = key.public_key.as_ref()?;
+                Some(json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "x": x,
+                    "kid": kid,
+                    "alg": "EdDSA",
+                    "use": "sig",
+                }))
+            })
+            .collect();
+        keys.sort_by(|a, b| a["kid"].as_str().cmp(&b["kid"].as_str()));
+        json!({ "keys": keys })
+    }
+
+    /// Sign `claims` with the active key, naming it in the `kid` header.
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, Error> {
+        let key = self
+            .keys
+            .get(&self.active)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?;
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.active.clone());
+        encode(&header, claims, &key.encoding)
+    }
+
+    /// Verify `token` with the key named by its `kid` header (`default` if it
+    /// has none). The algorithm is the key's, whatever `validation` says.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> Result<TokenData<T>, Error> {
+        let header = decode_header(token)?;
+        let kid = header.kid.as_deref().unwrap_or(DEFAULT_KID);
+        let key = self
+            .keys
+            .get(kid)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidToken))?;
+        let mut validation = validation.clone();
+        validation.algorithms = vec![key.algorithm];
+        decode(token, &key.decoding, &validation)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
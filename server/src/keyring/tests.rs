@@ -0,0 +1,148 @@
+use super::*;
+use crate::config::test_config;
+use chrono::Utc;
+use serde_json::{Value, json};
+
+fn claims() -> Value {
+    json!({ "sub": "pk", "exp": Utc::now().timestamp() + 60 })
+}
+
+fn keys_file(active: &str, kids: &[&str]) -> String {
+    let keys: Vec<Value> = kids
+        .iter()
+        .map(|kid| json!({ "kid": kid, "secret": format!("secret-{}", kid) }))
+        .collect();
+    json!({ "active": active, "keys": keys }).to_string()
+}
+
+#[test]
+fn test_rotation_keeps_old_tokens_valid() {
+    let old = Keyring::parse(&keys_file("2024", &["2024"])).unwrap();
+    let token = old.encode(&claims()).unwrap();
+    assert_eq!(decode_header(&token).unwrap().kid.as_deref(), Some("2024"));
+
+    let rotated = Keyring::parse(&keys_file("2025", &["2024", "2025"])).unwrap();
+    assert_eq!(rotated.active_kid(), "2025");
+    let validation = Validation::default();
+    assert!(rotated.decode::<Value>(&token, &validation).is_ok());
+    let new_token = rotated.encode(&claims()).unwrap();
+    assert_eq!(
+        decode_header(&new_token).unwrap().kid.as_deref(),
+        Some("2025")
+    );
+    assert!(old.decode::<Value>(&new_token, &validation).is_err());
+
+    // Dropping the old key retires its tokens
+    let retired = Keyring::parse(&keys_file("2025", &["2025"])).unwrap();
+    assert!(retired.decode::<Value>(&token, &validation).is_err());
+    assert!(retired.decode::<Value>(&new_token, &validation).is_ok());
+}
+
+#[test]
+fn test_tokens_without_kid_use_default_key() {
+    let config = test_config();
+    let secret = config.jwt_secret.as_deref().unwrap();
+    let legacy = encode(
+        &Header::default(),
+        &claims(),
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap();
+    let validation = Validation::default();
+    assert!(config.keyring.decode::<Value>(&legacy, &validation).is_ok());
+    let rotated = Keyring::parse(&keys_file("2025", &["2025"])).unwrap();
+    assert!(rotated.decode::<Value>(&legacy, &validation).is_err());
+}
+
+#[test]
+fn test_keys_file_keeps_jwt_secret_as_default() {
+    let path = std::env::temp_dir().join(format!("keys-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(&path, keys_file("2025", &["2025"])).unwrap();
+    let mut config = test_config();
+    let token = config.keyring.encode(&claims()).unwrap();
+    config.jwt_keys_file = Some(path.to_string_lossy().into_owned());
+    let keyring = Keyring::from_config(&config).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(keyring.active_kid(), "2025");
+    assert!(
+        keyring
+            .decode::<Value>(&token, &Validation::default())
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_invalid_keys_files() {
+    assert!(Keyring::parse("not json").is_err());
+    assert!(Keyring::parse(&keys_file("2025", &["2024"])).is_err());
+    assert!(Keyring::parse(&keys_file("2024", &["2024", "2024"])).is_err());
+    let mut config = test_config();
+    config.jwt_secret = None;
+    assert!(Keyring::from_config(&config).is_err());
+}
+
+/// Write a fixed Ed25519 private key as PKCS#8 PEM to a temporary file.
+pub(crate) fn ed25519_key_file(seed: u8) -> std::path::PathBuf {
+    use ed25519_dalek::pkcs8::{EncodePrivateKey, spki::der::pem::LineEnding};
+    let pem = ed25519_dalek::SigningKey::from_bytes(&[seed; 32])
+        .to_pkcs8_pem(LineEnding::LF)
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("ed25519-{}.pem", uuid::Uuid::new_v4()));
+    std::fs::write(&path, pem.as_bytes()).unwrap();
+    path
+}
+
+#[test]
+fn test_eddsa_keyring() {
+    let key_file = ed25519_key_file(1);
+    let mut config = test_config();
+    let legacy = config.keyring.encode(&claims()).unwrap();
+    config.jwt_algorithm = "EdDSA".to_string();
+    config.jwt_ed25519_key_file = Some(key_file.to_string_lossy().into_owned());
+    let keyring = Keyring::from_config(&config).unwrap();
+    std::fs::remove_file(&key_file).unwrap();
+
+    let token = keyring.encode(&claims()).unwrap();
+    let header = decode_header(&token).unwrap();
+    assert_eq!(header.alg, Algorithm::EdDSA);
+    assert_eq!(header.kid.as_deref(), Some(keyring.active_kid()));
+    let validation = Validation::default();
+    assert!(keyring.decode::<Value>(&token, &validation).is_ok());
+    // HS256 tokens issued before the switch still verify
+    assert!(keyring.decode::<Value>(&legacy, &validation).is_ok());
+
+    // Only the Ed25519 public key is published
+    let jwks = keyring.jwks();
+    let keys = jwks["keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0]["kid"], keyring.active_kid());
+    let jwk: jsonwebtoken::jwk::Jwk = serde_json::from_value(keys[0].clone()).unwrap();
+    let mut validation = Validation::new(Algorithm::EdDSA);
+    validation.validate_aud = false;
+    let public = DecodingKey::from_jwk(&jwk).unwrap();
+    assert!(jsonwebtoken::decode::<Value>(&token, &public, &validation).is_ok());
+}
+
+#[test]
+fn test_keys_file_with_ed25519_key() {
+    let key_file = ed25519_key_file(2);
+    let contents = json!({
+        "active": "ed",
+        "keys": [
+            { "kid": "ed", "private_key_file": key_file },
+            { "kid": "hs", "secret": "secret-hs" },
+        ],
+    })
+    .to_string();
+    let keyring = Keyring::parse(&contents).unwrap();
+    std::fs::remove_file(&key_file).unwrap();
+    let token = keyring.encode(&claims()).unwrap();
+    assert_eq!(decode_header(&token).unwrap().alg, Algorithm::EdDSA);
+    assert_eq!(keyring.jwks()["keys"].as_array().unwrap().len(), 1);
+    // A key must be exactly one of the two kinds
+    let both = json!({
+        "active": "x",
+        "keys": [{ "kid": "x", "secret": "s", "private_key_file": "/nonexistent" }],
+    });
+    assert!(Keyring::parse(&both.to_string()).is_err());
+}
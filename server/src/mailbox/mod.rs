@@ -0,0 +1,185 @@
+//! Canonical mailbox identifiers.
+//!
+//! A mailbox is named by its age recipient: an X25519 key, an SSH key
+//! (`ssh-ed25519` or `ssh-rsa`) so that an existing `~/.ssh/id_ed25519` can
+//! prove possession, or with the `pq` feature a hybrid post-quantum key
+//! (`age1pq1...`). Clients send the key either as is or base64-wrapped (the
+//! bash client sends `base64("age1...")`), so every key is normalized before
+//! it is used as a JWT `sub`: X25519 keys to lowercase bech32, SSH keys to
+//! `<type> <base64>` without a comment.
+//!
+//! The database never sees the key itself: mailboxes are stored under an
+//! HMAC of the canonical key ([`storage_key`]), so a dump does not reveal
+//! which keys receive drops.
+
+use crate::config::Config;
+use crate::error::StorageError;
+use crate::storage::Storage;
+use age::{Recipient, ssh, x25519};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MailboxId(String);
+
+impl MailboxId {
+    /// Canonical form of the key.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The age recipient tokens for this mailbox are encrypted to.
+    pub fn recipient(&self) -> Box<dyn Recipient + Send> {
+        #[cfg(feature = "pq")]
+        if let Ok(recipient) = self.0.parse::<crate::pq::Recipient>() {
+            return Box::new(recipient);
+        }
+        match self.0.parse::<x25519::Recipient>() {
+            Ok(recipient) => Box::new(recipient),
+            Err(_) => Box::new(
+                self.0
+                    .parse::<ssh::Recipient>()
+                    .expect("MailboxId holds a valid recipient"),
+            ),
+        }
+    }
+
+    /// Type of the age header stanza that wraps a file key for this mailbox.
+    pub fn stanza_type(&self) -> &'static str {
+        #[cfg(feature = "pq")]
+        if self.0.starts_with("age1pq1") {
+            return crate::pq::STANZA_TAG;
+        }
+        match self.0.split_once(' ') {
+            Some(("ssh-rsa", _)) => "ssh-rsa",
+            Some(_) => "ssh-ed25519",
+            None => "X25519",
+        }
+    }
+
+    /// Parse an unwrapped key, `None` if `value` is not one.
+    fn from_key(value: &str) -> Option<Result<Self, &'static str>> {
+        #[cfg(feature = "pq")]
+        if value
+            .get(..7)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("age1pq1"))
+        {
+            let recipient = value.to_ascii_lowercase().parse::<crate::pq::Recipient>();
+            return Some(
+                recipient
+                    .map(|recipient| MailboxId(recipient.to_string()))
+                    .map_err(|_| "Invalid age ML-KEM-768 + X25519 pubkey"),
+            );
+        }
+        if value
+            .get(..4)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("age1"))
+        {
+            // bech32 is case-insensitive as long as the case is not mixed
+            let recipient = value.to_ascii_lowercase().parse::<x25519::Recipient>();
+            return Some(
+                recipient
+                    .map(|recipient| MailboxId(recipient.to_string()))
+                    .map_err(|_| "Invalid age X25519 pubkey"),
+            );
+        }
+        if value.starts_with("ssh-") {
+            let recipient = value.parse::<ssh::Recipient>();
+            return Some(
+                recipient
+                    .map(|recipient| MailboxId(recipient.to_string()))
+                    .map_err(|_| "Invalid or unsupported SSH pubkey"),
+            );
+        }
+        None
+    }
+}
+
+#[cfg(not(feature = "pq"))]
+const INVALID_KEY: &str = "Pubkey must be an age X25519 or SSH pubkey, optionally base64 encoded";
+#[cfg(feature = "pq")]
+const INVALID_KEY: &str = "Pubkey must be an age X25519, ML-KEM-768 + X25519 (age1pq1) or SSH pubkey, optionally base64 encoded";
+
+impl FromStr for MailboxId {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(result) = Self::from_key(value) {
+            return result;
+        }
+        [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+            .iter()
+            .find_map(|engine| engine.decode(value).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|inner| Self::from_key(inner.trim()))
+            .unwrap_or(Err(INVALID_KEY))
+    }
+}
+
+impl fmt::Display for MailboxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Secret from `MAILBOX_HASH_SECRET` keying [`storage_key`].
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MailboxHashKey(String);
+
+impl TryFrom<String> for MailboxHashKey {
+    type Error = &'static str;
+
+    fn try_from(secret: String) -> Result<Self, Self::Error> {
+        if secret.is_empty() {
+            return Err("MAILBOX_HASH_SECRET must not be empty");
+        }
+        Ok(MailboxHashKey(secret))
+    }
+}
+
+impl fmt::Debug for MailboxHashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MailboxHashKey(..)")
+    }
+}
+
+/// Identifier the mailbox of canonical key `pubkey` is stored under.
+pub fn storage_key(config: &Config, pubkey: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(config.mailbox_hash_secret.0.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(pubkey.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Replace raw pubkeys stored by older versions, in any spelling, with their
+/// [`storage_key`], merging spellings of the same key into one mailbox.
+/// Values that are not valid age recipients are left alone. Returns how many
+/// stored pubkeys were rewritten.
+pub async fn migrate_stored_ids(
+    config: &Config,
+    storage: &dyn Storage,
+) -> Result<u64, StorageError> {
+    let mut migrated = 0;
+    for pubkey in storage.unhashed_pubkeys().await? {
+        let Ok(mailbox) = pubkey.parse::<MailboxId>() else {
+            eprintln!("Skipping invalid stored pubkey {:?}", pubkey);
+            storage.skip_unhashed_pubkey(&pubkey).await?;
+            continue;
+        };
+        storage
+            .rename_mailbox(&pubkey, &storage_key(config, mailbox.as_str()))
+            .await?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
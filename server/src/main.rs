@@ -1,36 +1,89 @@
+mod agefile;
 pub mod auth;
+mod blob;
+mod capability;
 mod config;
+mod cursor;
 pub mod db;
+mod error;
 mod handlers;
+mod keyring;
+mod mailbox;
+mod pow;
+#[cfg(feature = "pq")]
+mod pq;
+mod ratelimit;
+mod replay;
+mod retention;
 mod routes;
+mod storage;
+mod telegram;
+mod uploads;
 
 use crate::config::{AppState, load_config};
-use sqlx::postgres::PgPoolOptions;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load configuration
-    let config = Arc::new(load_config().expect("Failed to load configuration"));
-    println!("Loaded config: {:?}", config);
-
-    // Create database connection pool
-    let db_pool = Arc::new(
-        PgPoolOptions::new()
-            .max_connections(5)
-            .connect(&config.database_url)
-            .await
-            .expect("Failed to create database pool"),
-    );
-    println!("Database pool created.");
+    let config = match load_config() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Not printed: the config holds secrets (JWT keys, database and S3 credentials, bot token)
+
+    // Connect to storage backend and run migrations
+    let storage = storage::connect(&config)
+        .await
+        .expect("Failed to initialize storage");
+    println!("Storage backend ready.");
+    let renamed = mailbox::migrate_stored_ids(&config, storage.as_ref())
+        .await
+        .expect("Failed to hash stored mailbox ids");
+    if renamed > 0 {
+        println!("Hashed {} stored mailbox ids.", renamed);
+    }
+
+    // Create blob store and move any ciphertexts still stored in the DB into it
+    let http_client = reqwest::Client::new();
+    let blobs = blob::from_config(&config, &http_client).expect("Failed to initialize blob store");
+    let migrated = blob::migrate_inline_ciphertexts(storage.as_ref(), blobs.as_ref())
+        .await
+        .expect("Failed to migrate inline ciphertexts");
+    if migrated > 0 {
+        println!("Moved {} inline ciphertexts to the blob store.", migrated);
+    }
+
+    // Request counters for rate limiting, local or shared through PostgreSQL
+    let rate_limiter = ratelimit::from_config(&config)
+        .await
+        .expect("Failed to initialize rate limiter");
+    ratelimit::spawn_sweeper(rate_limiter.clone());
+    // Used tokens of single-use scopes, local or shared through PostgreSQL
+    let seen_tokens = replay::from_config(&config)
+        .await
+        .expect("Failed to initialize seen token store");
+    replay::spawn_sweeper(seen_tokens.clone());
 
     // Create application state
     let app_state = AppState {
-        db_pool,
+        storage,
+        blobs,
         config: Arc::clone(&config),
+        http_client,
+        rate_limiter,
+        seen_tokens,
     };
 
+    // Discard abandoned resumable uploads and expired items in the background
+    uploads::spawn_gc(app_state.clone());
+    retention::spawn_sweeper(app_state.clone());
+
     // Create router
     let app = routes::create_router(app_state);
 
@@ -38,7 +91,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = format!("{}:{}", config.host, config.port);
     println!("Starting server on {}", addr);
     let listener = TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    // Peer addresses key the per-IP rate limits
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
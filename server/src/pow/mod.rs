@@ -0,0 +1,107 @@
+//! Hashcash-style proof of work for anonymous uploads.
+//!
+//! `POST /pow` hands out a stateless challenge: an HS256 JWT naming the
+//! recipient mailbox and the difficulty. The sender finds a counter such that
+//! SHA-256(`<challenge>:<counter>`) starts with `difficulty` zero bits and
+//! sends `X-PoW: <challenge>:<counter>` with the upload. Each challenge's
+//! nonce is recorded in the [`SeenTokenStore`](crate::replay::SeenTokenStore)
+//! once its stamp is accepted, so a stamp pays for a single upload.
+
+use crate::config::{AppState, Config};
+use crate::error::StorageError;
+use crate::mailbox::storage_key;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::Validation;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Request header carrying a solved stamp.
+pub const HEADER: &str = "X-PoW";
+
+/// Audience of PoW challenge JWTs, so they are never accepted as auth tokens.
+const AUDIENCE: &str = "/pow";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PowClaims {
+    sub: String, // recipient mailbox
+    aud: String,
+    exp: i64,
+    difficulty: u32,
+    nonce: Uuid, // makes every challenge unique; recorded once spent
+}
+
+/// Sign a new challenge for uploads to `pubkey`, returning it with its expiry.
+pub fn issue(
+    config: &Config,
+    pubkey: &str,
+    difficulty: u32,
+) -> Result<(String, DateTime<Utc>), jsonwebtoken::errors::Error> {
+    let expires_at = Utc::now() + Duration::seconds(config.pow_challenge_ttl_seconds);
+    let claims = PowClaims {
+        sub: pubkey.to_string(),
+        aud: AUDIENCE.to_string(),
+        exp: expires_at.timestamp(),
+        difficulty,
+        nonce: Uuid::new_v4(),
+    };
+    let challenge = config.keyring.encode(&claims)?;
+    Ok((challenge, expires_at))
+}
+
+/// Number of leading zero bits in `hash`.
+pub fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte != 0 {
+            return bits + byte.leading_zeros();
+        }
+        bits += 8;
+    }
+    bits
+}
+
+/// Check an `X-PoW` stamp for an upload to `pubkey` needing `required` bits.
+/// Returns the challenge nonce and expiry, for the caller to record as spent.
+pub fn verify(
+    config: &Config,
+    pubkey: &str,
+    stamp: &str,
+    required: u32,
+) -> Result<(Uuid, DateTime<Utc>), &'static str> {
+    let (challenge, counter) = stamp.rsplit_once(':').ok_or("Malformed X-PoW header")?;
+    let mut validation = Validation::default();
+    validation.set_audience(&[AUDIENCE]);
+    // Spent nonces are only remembered until `exp`, so allow no grace period
+    validation.leeway = 0;
+    let claims = config
+        .keyring
+        .decode::<PowClaims>(challenge, &validation)
+        .map_err(|_| "Invalid or expired PoW challenge")?
+        .claims;
+    if claims.sub != pubkey {
+        return Err("PoW challenge was issued for another mailbox");
+    }
+    // The mailbox may have raised its difficulty since the challenge was issued
+    if claims.difficulty < required {
+        return Err("PoW challenge difficulty is too low");
+    }
+    let hash = Sha256::digest(format!("{}:{}", challenge, counter).as_bytes());
+    if leading_zero_bits(&hash) < claims.difficulty {
+        return Err("Insufficient proof of work");
+    }
+    let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_default();
+    Ok((claims.nonce, expires_at))
+}
+
+/// Difficulty uploads to `pubkey` must meet: the deployment's `POW_DIFFICULTY`,
+/// or the mailbox's own setting if that is higher. 0 means no PoW is needed.
+pub async fn required_difficulty(state: &AppState, pubkey: &str) -> Result<u32, StorageError> {
+    let mailbox = storage_key(&state.config, pubkey);
+    let settings = state.storage.get_mailbox_settings(&mailbox).await?;
+    let mailbox = settings.pow_difficulty.unwrap_or(0).max(0) as u32;
+    Ok(state.config.pow_difficulty.max(mailbox))
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
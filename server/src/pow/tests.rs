@@ -0,0 +1,72 @@
+use super::*;
+use crate::config::test_config;
+
+/// Brute-force a counter for `challenge`, as a client would.
+pub(crate) fn solve(challenge: &str, difficulty: u32) -> String {
+    (0u64..)
+        .map(|counter| format!("{}:{}", challenge, counter))
+        .find(|stamp| leading_zero_bits(&Sha256::digest(stamp.as_bytes())) >= difficulty)
+        .unwrap()
+}
+
+#[test]
+fn test_leading_zero_bits() {
+    assert_eq!(leading_zero_bits(&[0xff]), 0);
+    assert_eq!(leading_zero_bits(&[0x00, 0x10]), 11);
+    assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+}
+
+#[test]
+fn test_issue_and_verify_stamp() {
+    let config = test_config();
+    let (challenge, expires_at) = issue(&config, "pk", 8).unwrap();
+    assert!(expires_at > Utc::now());
+    let stamp = solve(&challenge, 8);
+    let (nonce, stamp_expiry) = verify(&config, "pk", &stamp, 8).unwrap();
+    assert_eq!(stamp_expiry.timestamp(), expires_at.timestamp());
+    assert_eq!(verify(&config, "pk", &stamp, 4), Ok((nonce, stamp_expiry)));
+    assert!(verify(&config, "other", &stamp, 8).is_err());
+    assert!(verify(&config, "pk", &stamp, 9).is_err());
+    assert!(verify(&config, "pk", &challenge, 8).is_err());
+
+    // An unsolved counter fails unless it happens to meet the difficulty
+    let unsolved = (0u64..)
+        .map(|counter| format!("{}:{}", challenge, counter))
+        .find(|stamp| leading_zero_bits(&Sha256::digest(stamp.as_bytes())) < 8)
+        .unwrap();
+    assert_eq!(
+        verify(&config, "pk", &unsolved, 8),
+        Err("Insufficient proof of work")
+    );
+}
+
+#[test]
+fn test_verify_rejects_other_tokens() {
+    let config = test_config();
+    // A regular auth JWT is signed with the same secret but has another audience
+    let claims = crate::auth::AuthClaims::new(
+        "pk".to_string(),
+        "/retrieve".to_string(),
+        Utc::now().timestamp() + 60,
+        Utc::now().timestamp(),
+        None,
+    );
+    let jwt = crate::auth::create_challenge_jwt(&claims, &config).unwrap();
+    assert!(verify(&config, "pk", &format!("{}:0", jwt), 0).is_err());
+
+    let mut other = config.clone();
+    other.keyring = crate::keyring::Keyring::from_secret("another_secret");
+    let (challenge, _) = issue(&other, "pk", 0).unwrap();
+    assert!(verify(&config, "pk", &format!("{}:0", challenge), 0).is_err());
+}
+
+#[test]
+fn test_expired_challenge_is_rejected_without_leeway() {
+    let mut config = test_config();
+    config.pow_challenge_ttl_seconds = -1;
+    let (challenge, _) = issue(&config, "pk", 0).unwrap();
+    assert_eq!(
+        verify(&config, "pk", &format!("{}:0", challenge), 0),
+        Err("Invalid or expired PoW challenge")
+    );
+}
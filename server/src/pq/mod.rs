@@ -0,0 +1,118 @@
+//! The `mlkem768x25519` hybrid post-quantum age recipient (`age1pq1...`),
+//! for mailboxes whose drops must stay confidential against an attacker who
+//! records them now and decrypts them with a quantum computer later.
+//!
+//! The file key is sealed with HPKE (RFC 9180, base mode) using the X-Wing
+//! KEM (X25519 + ML-KEM-768), HKDF-SHA256 and ChaCha20-Poly1305. age 0.11
+//! has no such recipient, so it is implemented here; the server only ever
+//! encrypts to it.
+
+use age::EncryptError;
+use age_core::format::{FileKey, Stanza};
+use age_core::secrecy::ExposeSecret;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use bech32::{FromBase32, ToBase32, Variant};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fmt;
+use x_wing::{Encapsulate, EncapsulationKey, KeyExport};
+
+const RECIPIENT_PREFIX: &str = "age1pq";
+pub(crate) const STANZA_TAG: &str = "mlkem768x25519";
+const HPKE_INFO: &[u8] = b"age-encryption.org/mlkem768x25519";
+// HPKE suite: X-Wing KEM (0x647a), HKDF-SHA256 (0x0001), ChaCha20Poly1305 (0x0003)
+const SUITE_ID: &[u8] = b"HPKE\x64\x7a\x00\x01\x00\x03";
+
+/// A hybrid X25519 + ML-KEM-768 recipient.
+#[derive(Clone)]
+pub struct Recipient(EncapsulationKey);
+
+impl std::str::FromStr for Recipient {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data, variant) = bech32::decode(s).map_err(|_| "invalid Bech32 encoding")?;
+        if hrp != RECIPIENT_PREFIX || variant != Variant::Bech32 {
+            return Err("incorrect HRP");
+        }
+        let bytes = Vec::<u8>::from_base32(&data).map_err(|_| "invalid Bech32 encoding")?;
+        EncapsulationKey::try_from(&bytes[..])
+            .map(Recipient)
+            .map_err(|_| "invalid ML-KEM-768 + X25519 pubkey")
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = bech32::encode(
+            RECIPIENT_PREFIX,
+            self.0.to_bytes().to_base32(),
+            Variant::Bech32,
+        )
+        .expect("HRP is valid");
+        f.write_str(&encoded)
+    }
+}
+
+impl age::Recipient for Recipient {
+    fn wrap_file_key(
+        &self,
+        file_key: &FileKey,
+    ) -> Result<(Vec<Stanza>, HashSet<String>), EncryptError> {
+        let (enc, shared_secret) = self.0.encapsulate();
+        let (key, nonce) = key_schedule(&shared_secret);
+        let body = ChaCha20Poly1305::new(&key)
+            .encrypt(&nonce, &file_key.expose_secret()[..])
+            .expect("encrypting a file key cannot fail");
+        Ok((
+            vec![Stanza {
+                tag: STANZA_TAG.to_owned(),
+                args: vec![STANDARD_NO_PAD.encode(enc)],
+                body,
+            }],
+            // Never mix with classic recipients, which would void the PQ security
+            HashSet::from(["postquantum".to_owned()]),
+        ))
+    }
+}
+
+/// HPKE `KeySchedule` in base mode for [`HPKE_INFO`]: the AEAD key and the
+/// nonce of the first (and only) message.
+fn key_schedule(shared_secret: &[u8]) -> (Key, Nonce) {
+    hpke_key_schedule(SUITE_ID, HPKE_INFO, shared_secret)
+}
+
+/// [`key_schedule`] for any HKDF-SHA256 + ChaCha20Poly1305 suite, so it can be
+/// checked against the RFC 9180 test vectors.
+fn hpke_key_schedule(suite_id: &[u8], info: &[u8], shared_secret: &[u8]) -> (Key, Nonce) {
+    let psk_id_hash = labeled_extract(suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(suite_id, b"", b"info_hash", info);
+    let context = [&[0u8][..], &psk_id_hash, &info_hash].concat();
+    let secret = labeled_extract(suite_id, shared_secret, b"secret", b"");
+    let mut key = Key::default();
+    labeled_expand(suite_id, &secret, b"key", &context, &mut key);
+    let mut nonce = Nonce::default();
+    labeled_expand(suite_id, &secret, b"base_nonce", &context, &mut nonce);
+    (key, nonce)
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> [u8; 32] {
+    let labeled_ikm = [b"HPKE-v1", suite_id, label, ikm].concat();
+    Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm).0.into()
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8; 32], label: &[u8], info: &[u8], out: &mut [u8]) {
+    let length = (out.len() as u16).to_be_bytes();
+    let labeled_info = [&length[..], b"HPKE-v1", suite_id, label, info].concat();
+    Hkdf::<Sha256>::from_prk(prk)
+        .expect("PRK has the hash length")
+        .expand(&labeled_info, out)
+        .expect("output fits HKDF-SHA256");
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
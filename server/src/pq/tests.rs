@@ -0,0 +1,154 @@
+use super::*;
+use age::{DecryptError, Decryptor, Encryptor, Identity};
+use std::io::{Read, Write};
+use x_wing::{Decapsulate, DecapsulationKey, Decapsulator, Generate};
+
+/// Decrypting side of [`Recipient`], which the server itself never needs.
+pub(crate) struct TestIdentity(DecapsulationKey);
+
+impl TestIdentity {
+    pub(crate) fn generate() -> Self {
+        TestIdentity(DecapsulationKey::generate())
+    }
+
+    pub(crate) fn to_public(&self) -> Recipient {
+        Recipient(self.0.encapsulation_key().clone())
+    }
+}
+
+impl Identity for TestIdentity {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        if stanza.tag != STANZA_TAG {
+            return None;
+        }
+        let enc = match &stanza.args[..] {
+            [arg] => STANDARD_NO_PAD.decode(arg).ok(),
+            _ => None,
+        };
+        let Some(Ok(enc)) = enc.map(|enc| x_wing::Ciphertext::try_from(&enc[..])) else {
+            return Some(Err(DecryptError::InvalidHeader));
+        };
+        let (key, nonce) = key_schedule(&self.0.decapsulate(&enc));
+        ChaCha20Poly1305::new(&key)
+            .decrypt(&nonce, &stanza.body[..])
+            .ok()
+            .filter(|pt| pt.len() == 16)
+            .map(|pt| {
+                Ok(FileKey::init_with_mut(|file_key| {
+                    file_key.copy_from_slice(&pt)
+                }))
+            })
+    }
+}
+
+fn encrypt(recipient: &Recipient, plaintext: &[u8]) -> Vec<u8> {
+    let encryptor =
+        Encryptor::with_recipients(std::iter::once(recipient as &dyn age::Recipient)).unwrap();
+    let mut ciphertext = vec![];
+    let mut writer = encryptor.wrap_output(&mut ciphertext).unwrap();
+    writer.write_all(plaintext).unwrap();
+    writer.finish().unwrap();
+    ciphertext
+}
+
+fn decrypt(identity: &dyn Identity, ciphertext: &[u8]) -> Result<Vec<u8>, DecryptError> {
+    let decryptor = Decryptor::new(ciphertext)?;
+    let mut reader = decryptor.decrypt(std::iter::once(identity))?;
+    let mut plaintext = vec![];
+    reader.read_to_end(&mut plaintext).unwrap();
+    Ok(plaintext)
+}
+
+#[test]
+fn test_recipient_encoding() {
+    let recipient = TestIdentity::generate().to_public().to_string();
+    assert!(recipient.starts_with("age1pq1"));
+    let parsed: Recipient = recipient.parse().unwrap();
+    assert_eq!(parsed.to_string(), recipient);
+
+    let x25519 = age::x25519::Identity::generate().to_public().to_string();
+    assert!(x25519.parse::<Recipient>().is_err());
+    assert!(
+        recipient[..recipient.len() - 1]
+            .parse::<Recipient>()
+            .is_err()
+    );
+}
+
+#[test]
+fn test_round_trip() {
+    let identity = TestIdentity::generate();
+    let ciphertext = encrypt(&identity.to_public(), b"harvest now, decrypt never");
+    assert_eq!(
+        decrypt(&identity, &ciphertext).unwrap(),
+        b"harvest now, decrypt never"
+    );
+    // Another key cannot open it
+    assert!(decrypt(&TestIdentity::generate(), &ciphertext).is_err());
+}
+
+/// X-Wing vectors from draft-connolly-cfrg-xwing-kem, as published at
+/// <https://github.com/dconnolly/draft-connolly-cfrg-xwing-kem/blob/main/spec/test-vectors.json>
+#[derive(serde::Deserialize)]
+struct XWingVector {
+    sk: String,
+    pk: String,
+    eseed: String,
+    ct: String,
+    ss: String,
+}
+
+#[test]
+fn test_x_wing_vectors() {
+    let vectors: Vec<XWingVector> =
+        serde_json::from_str(include_str!("testdata/xwing.json")).unwrap();
+    for vector in vectors {
+        let sk: [u8; 32] = hex::decode(&vector.sk).unwrap().try_into().unwrap();
+        let identity = TestIdentity(DecapsulationKey::from(sk));
+        let pk = hex::decode(&vector.pk).unwrap();
+        assert_eq!(&*identity.to_public().0.to_bytes(), &pk[..]);
+
+        // Published keys parse as recipients and survive the Bech32 round trip
+        let encoded = bech32::encode(RECIPIENT_PREFIX, pk.to_base32(), Variant::Bech32).unwrap();
+        let recipient: Recipient = encoded.parse().unwrap();
+        assert_eq!(recipient.to_string(), encoded);
+
+        let eseed = hex::decode(&vector.eseed).unwrap();
+        let (ct, ss) = recipient
+            .0
+            .encapsulate_deterministic(&eseed[..].try_into().unwrap());
+        assert_eq!(hex::encode(ct), vector.ct);
+        assert_eq!(hex::encode(ss), vector.ss);
+        assert_eq!(hex::encode(identity.0.decapsulate(&ct)), vector.ss);
+    }
+}
+
+/// RFC 9180 A.2.1: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, ChaCha20Poly1305,
+/// which shares its KDF and AEAD with the X-Wing suite.
+#[test]
+fn test_hpke_key_schedule_vector() {
+    let shared_secret =
+        hex::decode("0bbe78490412b4bbea4812666f7916932b828bba79942424abb65244930d69a7").unwrap();
+    let info = hex::decode("4f6465206f6e2061204772656369616e2055726e").unwrap();
+    let (key, nonce) = hpke_key_schedule(b"HPKE\x00\x20\x00\x01\x00\x03", &info, &shared_secret);
+    assert_eq!(
+        hex::encode(key),
+        "ad2744de8e17f4ebba575b3f5f5a8fa1f69c2a07f6e7500bc60ca6e3e3ec1c91"
+    );
+    assert_eq!(hex::encode(nonce), "5c4d98150661b848853b547f");
+
+    // Sequence number 0 seals with the base nonce as is
+    let ct = ChaCha20Poly1305::new(&key)
+        .encrypt(
+            &nonce,
+            chacha20poly1305::aead::Payload {
+                msg: b"Beauty is truth, truth beauty",
+                aad: b"Count-0",
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        hex::encode(ct),
+        "1c5250d8034ec2b784ba2cfd69dbdb8af406cfe3ff938e131f0def8c8b60b4db21993c62ce81883d2dd1b51a28"
+    );
+}
@@ -0,0 +1,58 @@
+use super::{Decision, RateLimit, RateLimitStore};
+use crate::error::StorageError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub(super) struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant, // when the bucket has refilled completely and can be dropped
+}
+
+/// Token buckets in process memory; each instance enforces its own limits.
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    pub(super) buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn check(&self, key: &str, limit: RateLimit) -> Result<Decision, StorageError> {
+        let capacity = limit.requests as f64;
+        let per_second = capacity / limit.period.as_secs_f64();
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            full_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / per_second);
+            return Ok(Decision::Limited { retry_after });
+        }
+        bucket.tokens -= 1.0;
+        bucket.full_at = now + Duration::from_secs_f64((capacity - bucket.tokens) / per_second);
+        Ok(Decision::Allowed)
+    }
+
+    async fn sweep(&self) -> Result<(), StorageError> {
+        let now = Instant::now();
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| bucket.full_at > now);
+        Ok(())
+    }
+}
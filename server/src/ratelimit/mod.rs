@@ -0,0 +1,211 @@
+//! Per-route request limits, keyed by client IP and by mailbox (the `sub` of
+//! a valid bearer token). Limits live in a [`RateLimitStore`]:
+//! token buckets in process memory, or fixed-window counters in PostgreSQL
+//! shared by every instance.
+
+pub mod memory;
+pub mod postgres;
+
+use crate::auth::verified_subject;
+use crate::config::{AppState, Config};
+use crate::error::StorageError;
+use crate::mailbox::storage_key;
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{HeaderMap, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::headers::{Authorization, HeaderMapExt, authorization::Bearer};
+use serde::{Deserialize, Deserializer};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use memory::MemoryRateLimitStore;
+pub use postgres::PgRateLimitStore;
+
+/// How often stale counters are dropped from the store.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `requests` per `period` for one key, written `<requests>/<seconds>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, period_seconds: u64) -> Self {
+        RateLimit {
+            requests,
+            period: Duration::from_secs(period_seconds),
+        }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid rate limit {:?}, expected <requests>/<seconds>",
+                value
+            )
+        };
+        let (requests, seconds) = value.trim().split_once('/').ok_or_else(invalid)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| invalid())?;
+        let seconds: u64 = seconds.trim().parse().map_err(|_| invalid())?;
+        if requests == 0 || seconds == 0 {
+            return Err(invalid());
+        }
+        Ok(RateLimit::new(requests, seconds))
+    }
+}
+
+/// Deserialize an optional [`RateLimit`] from config, where `off` disables it.
+pub fn deserialize_limit<'de, D>(deserializer: D) -> Result<Option<RateLimit>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    if value.trim().eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Outcome of counting one request against a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+/// Backend keeping request counts per key.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Count one request for `key` and decide whether it is within `limit`.
+    async fn check(&self, key: &str, limit: RateLimit) -> Result<Decision, StorageError>;
+
+    /// Forget counters that no longer affect any decision.
+    async fn sweep(&self) -> Result<(), StorageError>;
+}
+
+/// Build the store selected by `RATE_LIMIT_BACKEND` (`memory` or `postgres`).
+pub async fn from_config(config: &Config) -> Result<Arc<dyn RateLimitStore>, StorageError> {
+    match config.rate_limit_backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryRateLimitStore::new())),
+        "postgres" => {
+            let url = config
+                .rate_limit_database_url
+                .as_deref()
+                .unwrap_or(&config.database_url);
+            Ok(Arc::new(PgRateLimitStore::connect(url).await?))
+        }
+        other => Err(StorageError::Config(format!(
+            "Unknown rate limit backend: {}",
+            other
+        ))),
+    }
+}
+
+/// The route group a matched path belongs to and its configured limit.
+fn route_limit(config: &Config, path: &str) -> Option<(&'static str, RateLimit)> {
+    let (group, limit) = match path {
+        "/upload" | "/uploads" | "/uploads/{upload_id}" | "/uploads/{upload_id}/finalize" => {
+            ("upload", config.rate_limit_upload)
+        }
+        "/challenge" | "/pow" => ("challenge", config.rate_limit_challenge),
+        "/retrieve" | "/download/{item_id}" | "/ack" | "/usage" | "/settings" => {
+            ("retrieve", config.rate_limit_retrieve)
+        }
+        "/items" | "/items/{item_id}" => ("delete", config.rate_limit_delete),
+        "/notify" => ("notify", config.rate_limit_notify),
+        _ => return None,
+    };
+    limit.map(|limit| (group, limit))
+}
+
+/// Client address: the first `X-Forwarded-For` hop when the server runs
+/// behind a trusted proxy, otherwise the peer address.
+fn client_ip(config: &Config, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+    if config.rate_limit_trust_proxy
+        && let Some(ip) = headers
+            .get("X-Forwarded-For")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .and_then(|v| v.trim().parse().ok())
+    {
+        return Some(ip);
+    }
+    peer.map(|addr| addr.ip())
+}
+
+/// Mailbox a request acts for: the subject of a valid bearer token. Keys
+/// named by unauthenticated requests (`X-PubKey`, a `/challenge` body) are
+/// not used, since anyone could drain the owner's allowance with them.
+fn client_mailbox(config: &Config, headers: &HeaderMap) -> Option<String> {
+    let auth = headers.typed_get::<Authorization<Bearer>>()?;
+    verified_subject(auth.token(), config).map(|sub| storage_key(config, &sub))
+}
+
+/// Middleware enforcing the route's limit per client IP and per mailbox.
+/// Exceeding either answers `429 Too Many Requests` with `Retry-After`.
+pub async fn limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let Some((group, limit)) = route_limit(&state.config, path.as_str()) else {
+        return next.run(request).await;
+    };
+    let headers = request.headers();
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    let mut keys = Vec::new();
+    if let Some(ip) = client_ip(&state.config, headers, peer) {
+        keys.push(format!("{}:ip:{}", group, ip));
+    }
+    if let Some(mailbox) = client_mailbox(&state.config, headers) {
+        keys.push(format!("{}:mailbox:{}", group, mailbox));
+    }
+    for key in keys {
+        match state.rate_limiter.check(&key, limit).await {
+            Ok(Decision::Allowed) => {}
+            Ok(Decision::Limited { retry_after }) => {
+                // Round up so clients never retry too early
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, seconds.max(1).to_string())],
+                    "Too many requests",
+                )
+                    .into_response();
+            }
+            // Fail open: an unavailable limiter must not take the service down
+            Err(e) => eprintln!("Rate limiter error: {}", e),
+        }
+    }
+    next.run(request).await
+}
+
+/// Run [`RateLimitStore::sweep`] periodically in the background.
+pub fn spawn_sweeper(store: Arc<dyn RateLimitStore>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = store.sweep().await {
+                eprintln!("Rate limit sweep failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,76 @@
+use super::{Decision, RateLimit, RateLimitStore};
+use crate::error::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, postgres::PgPoolOptions};
+use std::time::Duration;
+
+/// Fixed-window counters in a PostgreSQL table, so every instance pointed at
+/// the same database shares one limit per key. Unlike the token buckets of
+/// [`super::MemoryRateLimitStore`], a client can send up to twice the limit
+/// across a window boundary.
+pub struct PgRateLimitStore {
+    pool: PgPool,
+}
+
+impl PgRateLimitStore {
+    /// Connect and create the counter table if needed.
+    pub async fn connect(database_url: &str) -> Result<Self, StorageError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+        pool.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS rate_limits (
+                key TEXT PRIMARY KEY,
+                window_start TIMESTAMPTZ NOT NULL,
+                window_end TIMESTAMPTZ NOT NULL,
+                count INTEGER NOT NULL
+            )
+        "#,
+        )
+        .await?;
+        Ok(PgRateLimitStore { pool })
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PgRateLimitStore {
+    async fn check(&self, key: &str, limit: RateLimit) -> Result<Decision, StorageError> {
+        let now = Utc::now();
+        let period = limit.period.as_secs() as i64;
+        let window_start =
+            DateTime::from_timestamp(now.timestamp() - now.timestamp().rem_euclid(period), 0)
+                .unwrap_or(now);
+        let window_end = window_start + chrono::Duration::seconds(period);
+        // Restart the count when the stored window has passed
+        let (count,): (i32,) = sqlx::query_as(
+            "INSERT INTO rate_limits (key, window_start, window_end, count) VALUES ($1, $2, $3, 1) \
+             ON CONFLICT (key) DO UPDATE SET \
+                 count = CASE WHEN rate_limits.window_start = EXCLUDED.window_start \
+                     THEN rate_limits.count + 1 ELSE 1 END, \
+                 window_start = EXCLUDED.window_start, \
+                 window_end = EXCLUDED.window_end \
+             RETURNING count",
+        )
+        .bind(key)
+        .bind(window_start)
+        .bind(window_end)
+        .fetch_one(&self.pool)
+        .await?;
+        if count as u32 <= limit.requests {
+            return Ok(Decision::Allowed);
+        }
+        let retry_after = (window_end - now).to_std().unwrap_or(Duration::ZERO);
+        Ok(Decision::Limited { retry_after })
+    }
+
+    async fn sweep(&self) -> Result<(), StorageError> {
+        sqlx::query("DELETE FROM rate_limits WHERE window_end < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use super::*;
+use crate::config::test_config;
+use uuid::Uuid;
+
+#[test]
+fn test_parse_rate_limit() {
+    assert_eq!("10/60".parse(), Ok(RateLimit::new(10, 60)));
+    assert_eq!(" 5 / 1 ".parse(), Ok(RateLimit::new(5, 1)));
+    assert!("10".parse::<RateLimit>().is_err());
+    assert!("0/60".parse::<RateLimit>().is_err());
+    assert!("10/0".parse::<RateLimit>().is_err());
+    assert!("ten/60".parse::<RateLimit>().is_err());
+}
+
+#[test]
+fn test_config_rate_limits() {
+    let config: Config = envy::from_iter([
+        ("DATABASE_URL".to_string(), "memory:".to_string()),
+        ("MAILBOX_HASH_SECRET".to_string(), "secret".to_string()),
+        ("JWT_SECRET".to_string(), "secret".to_string()),
+        ("RATE_LIMIT_CHALLENGE".to_string(), "3/10".to_string()),
+        ("RATE_LIMIT_NOTIFY".to_string(), "off".to_string()),
+    ])
+    .unwrap();
+    assert_eq!(config.rate_limit_challenge, Some(RateLimit::new(3, 10)));
+    assert_eq!(config.rate_limit_notify, None);
+    assert_eq!(config.rate_limit_upload, Some(RateLimit::new(60, 60)));
+    assert!(
+        envy::from_iter::<_, Config>([
+            ("DATABASE_URL".to_string(), "memory:".to_string()),
+            ("JWT_SECRET".to_string(), "secret".to_string()),
+            ("RATE_LIMIT_UPLOAD".to_string(), "lots".to_string()),
+        ])
+        .is_err()
+    );
+}
+
+#[test]
+fn test_client_ip_respects_trust_proxy() {
+    let mut config = test_config();
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Forwarded-For", "203.0.113.7, 10.0.0.1".parse().unwrap());
+    let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+    assert_eq!(client_ip(&config, &headers, Some(peer)), Some(peer.ip()));
+    config.rate_limit_trust_proxy = true;
+    assert_eq!(
+        client_ip(&config, &headers, Some(peer)),
+        Some("203.0.113.7".parse().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn test_memory_token_bucket() {
+    let store = MemoryRateLimitStore::new();
+    let limit = RateLimit::new(2, 60);
+    assert_eq!(store.check("a", limit).await.unwrap(), Decision::Allowed);
+    assert_eq!(store.check("a", limit).await.unwrap(), Decision::Allowed);
+    let Decision::Limited { retry_after } = store.check("a", limit).await.unwrap() else {
+        panic!("third request should be limited");
+    };
+    // One token refills every 30 seconds
+    assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+    assert_eq!(store.check("b", limit).await.unwrap(), Decision::Allowed);
+
+    // Partially drained buckets survive a sweep
+    store.sweep().await.unwrap();
+    assert!(matches!(
+        store.check("a", limit).await.unwrap(),
+        Decision::Limited { .. }
+    ));
+}
+
+#[tokio::test]
+async fn test_memory_bucket_refills() {
+    let store = MemoryRateLimitStore::new();
+    let limit = RateLimit {
+        requests: 1,
+        period: Duration::from_millis(20),
+    };
+    assert_eq!(store.check("a", limit).await.unwrap(), Decision::Allowed);
+    assert!(matches!(
+        store.check("a", limit).await.unwrap(),
+        Decision::Limited { .. }
+    ));
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    assert_eq!(store.check("a", limit).await.unwrap(), Decision::Allowed);
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    store.sweep().await.unwrap();
+    assert!(store.buckets.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_postgres_shared_counter() {
+    dotenvy::dotenv().ok();
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
+    // Two stores stand in for two server instances
+    let first = PgRateLimitStore::connect(&url).await.unwrap();
+    let second = PgRateLimitStore::connect(&url).await.unwrap();
+    let key = format!("test:{}", Uuid::new_v4());
+    let limit = RateLimit::new(3, 3600);
+    assert_eq!(first.check(&key, limit).await.unwrap(), Decision::Allowed);
+    assert_eq!(second.check(&key, limit).await.unwrap(), Decision::Allowed);
+    assert_eq!(first.check(&key, limit).await.unwrap(), Decision::Allowed);
+    let Decision::Limited { retry_after } = second.check(&key, limit).await.unwrap() else {
+        panic!("fourth request should be limited");
+    };
+    assert!(retry_after <= Duration::from_secs(3600));
+    first.sweep().await.unwrap();
+}
@@ -0,0 +1,54 @@
+use super::SeenTokenStore;
+use crate::error::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Seen tokens in a bounded map local to this instance. Only expired entries
+/// make room for new ones: when the map is full of unexpired tokens, new
+/// tokens are refused rather than forgetting one that could then be replayed.
+/// The capacity should cover the single-use tokens issued per
+/// `JWT_EXPIRATION_SECONDS`.
+pub struct MemorySeenTokenStore {
+    pub(super) seen: Mutex<HashMap<Uuid, DateTime<Utc>>>,
+    capacity: usize,
+}
+
+impl MemorySeenTokenStore {
+    pub fn new(capacity: usize) -> Self {
+        MemorySeenTokenStore {
+            seen: Mutex::new(HashMap::new()),
+            capacity: capacity.max(1),
+        }
+    }
+}
+
+#[async_trait]
+impl SeenTokenStore for MemorySeenTokenStore {
+    async fn first_use(&self, jti: Uuid, expires_at: DateTime<Utc>) -> Result<bool, StorageError> {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains_key(&jti) {
+            return Ok(false);
+        }
+        if seen.len() >= self.capacity {
+            let now = Utc::now();
+            seen.retain(|_, expires_at| *expires_at > now);
+            if seen.len() >= self.capacity {
+                return Err(StorageError::Full);
+            }
+        }
+        seen.insert(jti, expires_at);
+        Ok(true)
+    }
+
+    async fn sweep(&self) -> Result<(), StorageError> {
+        let now = Utc::now();
+        self.seen
+            .lock()
+            .unwrap()
+            .retain(|_, expires_at| *expires_at > now);
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+//! Replay protection for challenge JWTs. Tokens of the scopes listed in
+//! `SINGLE_USE_SCOPES` are recorded by `jti` in a [`SeenTokenStore`] the first
+//! time they are presented and rejected afterwards; tokens of other scopes
+//! stay reusable until `exp`. Entries are kept until their token expires.
+
+pub mod memory;
+pub mod postgres;
+
+use crate::config::Config;
+use crate::error::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub use memory::MemorySeenTokenStore;
+pub use postgres::PgSeenTokenStore;
+
+/// How often expired entries are dropped from the store.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Backend remembering the `jti` of tokens already used.
+#[async_trait]
+pub trait SeenTokenStore: Send + Sync {
+    /// Record `jti` as used until `expires_at`; false if it was seen before.
+    async fn first_use(&self, jti: Uuid, expires_at: DateTime<Utc>) -> Result<bool, StorageError>;
+
+    /// Forget tokens that have expired, since they fail verification anyway.
+    async fn sweep(&self) -> Result<(), StorageError>;
+}
+
+/// Whether tokens for `scope` may only be used once.
+pub fn is_single_use(config: &Config, scope: &str) -> bool {
+    config.single_use_scopes.iter().any(|s| s.trim() == scope)
+}
+
+/// Build the store selected by `REPLAY_BACKEND` (`memory` or `postgres`).
+pub async fn from_config(config: &Config) -> Result<Arc<dyn SeenTokenStore>, StorageError> {
+    match config.replay_backend.as_str() {
+        "memory" => Ok(Arc::new(MemorySeenTokenStore::new(
+            config.replay_cache_capacity,
+        ))),
+        "postgres" => {
+            let url = config
+                .replay_database_url
+                .as_deref()
+                .unwrap_or(&config.database_url);
+            Ok(Arc::new(PgSeenTokenStore::connect(url).await?))
+        }
+        other => Err(StorageError::Config(format!(
+            "Unknown replay backend: {}",
+            other
+        ))),
+    }
+}
+
+/// Sweep expired entries from `store` in the background.
+pub fn spawn_sweeper(store: Arc<dyn SeenTokenStore>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = store.sweep().await {
+                eprintln!("Seen token sweep failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests;
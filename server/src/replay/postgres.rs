@@ -0,0 +1,54 @@
+use super::SeenTokenStore;
+use crate::error::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, postgres::PgPoolOptions};
+use uuid::Uuid;
+
+/// Seen tokens in a PostgreSQL table, so a token used on one instance is
+/// rejected by every instance pointed at the same database.
+pub struct PgSeenTokenStore {
+    pool: PgPool,
+}
+
+impl PgSeenTokenStore {
+    /// Connect and create the table if needed.
+    pub async fn connect(database_url: &str) -> Result<Self, StorageError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+        pool.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS seen_tokens (
+                jti UUID PRIMARY KEY,
+                expires_at TIMESTAMPTZ NOT NULL
+            )
+        "#,
+        )
+        .await?;
+        Ok(PgSeenTokenStore { pool })
+    }
+}
+
+#[async_trait]
+impl SeenTokenStore for PgSeenTokenStore {
+    async fn first_use(&self, jti: Uuid, expires_at: DateTime<Utc>) -> Result<bool, StorageError> {
+        let result = sqlx::query(
+            "INSERT INTO seen_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(jti)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn sweep(&self) -> Result<(), StorageError> {
+        sqlx::query("DELETE FROM seen_tokens WHERE expires_at < $1")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
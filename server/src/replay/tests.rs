@@ -0,0 +1,55 @@
+use super::*;
+use crate::config::test_config;
+use chrono::Duration;
+
+#[test]
+fn test_single_use_scopes_config() {
+    let config: Config = envy::from_iter([
+        ("DATABASE_URL".to_string(), "memory:".to_string()),
+        ("MAILBOX_HASH_SECRET".to_string(), "secret".to_string()),
+        ("JWT_SECRET".to_string(), "secret".to_string()),
+        ("SINGLE_USE_SCOPES".to_string(), "notify,delete".to_string()),
+    ])
+    .unwrap();
+    assert!(is_single_use(&config, "delete"));
+    assert!(!is_single_use(&config, "retrieve"));
+    // Only notify tokens are single-use by default
+    let config = test_config();
+    assert!(is_single_use(&config, "notify"));
+    assert!(!is_single_use(&config, "delete"));
+}
+
+#[tokio::test]
+async fn test_memory_seen_tokens() {
+    let store = MemorySeenTokenStore::new(2);
+    let later = Utc::now() + Duration::minutes(5);
+    let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    assert!(store.first_use(a, later).await.unwrap());
+    assert!(!store.first_use(a, later).await.unwrap());
+    assert!(store.first_use(b, Utc::now()).await.unwrap());
+    store.sweep().await.unwrap();
+    assert_eq!(store.seen.lock().unwrap().len(), 1);
+    // Only expired entries make room; unexpired ones are never forgotten
+    assert!(store.first_use(b, Utc::now()).await.unwrap());
+    assert!(store.first_use(c, later).await.unwrap());
+    assert!(matches!(
+        store.first_use(b, later).await,
+        Err(StorageError::Full)
+    ));
+    assert!(!store.first_use(a, later).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_postgres_seen_tokens_are_shared() {
+    dotenvy::dotenv().ok();
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env");
+    // Two stores stand in for two server instances
+    let first = PgSeenTokenStore::connect(&url).await.unwrap();
+    let second = PgSeenTokenStore::connect(&url).await.unwrap();
+    let jti = Uuid::new_v4();
+    let expires_at = Utc::now() + Duration::minutes(5);
+    assert!(first.first_use(jti, expires_at).await.unwrap());
+    assert!(!second.first_use(jti, expires_at).await.unwrap());
+    first.sweep().await.unwrap();
+    assert!(!first.first_use(jti, expires_at).await.unwrap());
+}
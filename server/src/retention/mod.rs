@@ -0,0 +1,75 @@
+//! Item retention: a global max age (`MAX_ITEM_AGE_SECONDS`) and optional
+//! per-item TTLs requested by senders. Expired items are hidden by storage
+//! queries right away and purged, blobs included, by a background sweeper.
+
+use crate::blob;
+use crate::config::{AppState, Config};
+use crate::error::StorageError;
+use chrono::{DateTime, Duration, Utc};
+
+/// Items purged per storage round trip.
+const SWEEP_BATCH: u32 = 100;
+
+/// Expiry for a new item: the sender's requested TTL in seconds, capped by
+/// the global max age. `None` means the item never expires.
+pub fn expires_at(config: &Config, requested: Option<i64>) -> Option<DateTime<Utc>> {
+    let ttl = match (requested, config.max_item_age_seconds) {
+        (Some(requested), Some(max)) => requested.min(max),
+        (Some(ttl), None) | (None, Some(ttl)) => ttl,
+        (None, None) => return None,
+    };
+    Some(Utc::now() + Duration::seconds(ttl))
+}
+
+/// Delete every expired item and its blob, returning how many were purged.
+///
+/// Items created before the current max age are purged too, so lowering
+/// `MAX_ITEM_AGE_SECONDS` also applies to items stored under the old policy.
+pub async fn purge_expired(state: &AppState) -> Result<u64, StorageError> {
+    let now = Utc::now();
+    let created_before = state
+        .config
+        .max_item_age_seconds
+        .map(|max| now - Duration::seconds(max));
+    let mut purged = 0;
+    loop {
+        let batch = state
+            .storage
+            .expired_items(now, created_before, SWEEP_BATCH)
+            .await?;
+        if batch.is_empty() {
+            return Ok(purged);
+        }
+        for item in &batch {
+            if blob::delete_item_and_blob(state.storage.as_ref(), state.blobs.as_ref(), item)
+                .await?
+            {
+                purged += 1;
+            }
+        }
+    }
+}
+
+/// Run [`purge_expired`] every `EXPIRY_SWEEP_INTERVAL_SECONDS` in the background,
+/// along with dropping the use counters of expired upload capabilities,
+/// starting immediately.
+pub fn spawn_sweeper(state: AppState) {
+    let interval = std::time::Duration::from_secs(state.config.expiry_sweep_interval_seconds);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match purge_expired(&state).await {
+                Ok(0) => {}
+                Ok(n) => println!("Purged {} expired items.", n),
+                Err(e) => eprintln!("Expiry sweep failed: {}", e),
+            }
+            if let Err(e) = state.storage.delete_expired_capabilities(Utc::now()).await {
+                eprintln!("Capability sweep failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,78 @@
+use super::*;
+use crate::config::test_config;
+use crate::db::NewItem;
+use crate::storage::{DeliveryFilter, Quota};
+
+fn test_state(max_item_age_seconds: Option<i64>) -> AppState {
+    let mut config = test_config();
+    config.max_item_age_seconds = max_item_age_seconds;
+    crate::config::test_state(config)
+}
+
+async fn store_item(state: &AppState, data: &[u8], expires_at: Option<DateTime<Utc>>) -> String {
+    let key = state.blobs.put(data).await.unwrap();
+    let mut item = NewItem::new("pk", &key, data.len() as i64);
+    item.expires_at = expires_at;
+    state
+        .storage
+        .insert_item(&item, Quota::default())
+        .await
+        .unwrap();
+    key
+}
+
+#[test]
+fn test_expires_at_caps_requested_ttl() {
+    let mut config = test_config();
+    assert!(expires_at(&config, None).is_none());
+    let in_an_hour = expires_at(&config, Some(3600)).unwrap();
+    assert!(in_an_hour > Utc::now() + Duration::seconds(3590));
+
+    config.max_item_age_seconds = Some(60);
+    let capped = expires_at(&config, Some(3600)).unwrap();
+    assert!(capped <= Utc::now() + Duration::seconds(60));
+    assert!(expires_at(&config, None).is_some());
+    let shorter = expires_at(&config, Some(10)).unwrap();
+    assert!(shorter <= Utc::now() + Duration::seconds(10));
+}
+
+#[tokio::test]
+async fn test_purge_expired_items_and_blobs() {
+    let state = test_state(None);
+    let expired = store_item(&state, b"expired", Some(Utc::now() - Duration::seconds(1))).await;
+    let fresh = store_item(&state, b"fresh", Some(Utc::now() + Duration::hours(1))).await;
+    let forever = store_item(&state, b"forever", None).await;
+
+    // Expired items disappear from listings before the sweeper runs
+    assert_eq!(
+        state
+            .storage
+            .list_items("pk", DeliveryFilter::All, None, 10)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+
+    assert_eq!(purge_expired(&state).await.unwrap(), 1);
+    assert!(state.blobs.open(&expired).await.unwrap().is_none());
+    assert!(state.blobs.open(&fresh).await.unwrap().is_some());
+    assert!(state.blobs.open(&forever).await.unwrap().is_some());
+    assert_eq!(purge_expired(&state).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_purge_applies_global_max_age_to_existing_items() {
+    let state = test_state(Some(0));
+    store_item(&state, b"old", None).await;
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    assert_eq!(purge_expired(&state).await.unwrap(), 1);
+    assert!(
+        state
+            .storage
+            .list_items("pk", DeliveryFilter::All, None, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
@@ -0,0 +1,50 @@
+use crate::config::AppState;
+use crate::{handlers, ratelimit};
+use axum::{
+    Router, middleware,
+    routing::{delete, get, post},
+};
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/upload", post(handlers::upload::handle_upload))
+        .route("/uploads", post(handlers::uploads::handle_create_upload))
+        .route(
+            "/uploads/{upload_id}",
+            get(handlers::uploads::handle_upload_progress)
+                .put(handlers::uploads::handle_upload_chunk),
+        )
+        .route(
+            "/uploads/{upload_id}/finalize",
+            post(handlers::uploads::handle_finalize_upload),
+        )
+        .route("/pow", post(handlers::pow::handle_pow_challenge))
+        .route("/challenge", post(handlers::challenge::handle_challenge))
+        .route("/retrieve", post(handlers::retrieve::handle_retrieve))
+        .route("/ack", post(handlers::ack::handle_ack))
+        .route("/usage", get(handlers::usage::handle_usage))
+        .route(
+            "/download/{item_id}",
+            get(handlers::download::handle_download),
+        )
+        .route("/items", delete(handlers::delete::handle_bulk_delete))
+        .route(
+            "/items/{item_id}",
+            delete(handlers::delete::handle_delete_item),
+        )
+        .route("/notify", post(handlers::notify::handle_notify))
+        .route(
+            "/settings",
+            get(handlers::settings::handle_get_settings)
+                .put(handlers::settings::handle_put_settings),
+        )
+        .route("/.well-known/jwks.json", get(handlers::jwks::handle_jwks))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            ratelimit::limit,
+        ))
+        .with_state(app_state)
+}
+
+#[cfg(test)]
+mod tests;
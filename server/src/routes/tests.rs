@@ -0,0 +1,1480 @@
+use super::*;
+use crate::blob::MemoryBlobStore;
+use crate::config::{test_config, test_state};
+use crate::mailbox::storage_key;
+use crate::telegram::tests::start_mock_bot_api;
+use age::{Decryptor, Identity, x25519};
+use axum::body::{Body, Bytes};
+use axum::http::{Request, StatusCode, header};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE},
+};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Bytes) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, body)
+}
+
+async fn upload(app: &Router, pubkey: &str, body: &'static [u8]) -> StatusCode {
+    let request = Request::post("/upload")
+        .header("X-PubKey", pubkey)
+        .body(Body::from(body))
+        .unwrap();
+    send(app, request).await.0
+}
+
+/// Run the /challenge flow for `scope` and decrypt the returned JWT.
+async fn obtain_jwt(
+    app: &Router,
+    identity: &x25519::Identity,
+    scope: &str,
+    telegram: Option<&str>,
+) -> String {
+    let payload = json!({
+        "pubkey": identity.to_public().to_string(),
+        "scope": scope,
+        "telegram": telegram,
+    });
+    decrypt_challenge(app, identity, payload).await
+}
+
+/// POST `payload` to /challenge and decrypt the returned token.
+async fn decrypt_challenge(app: &Router, identity: &dyn Identity, payload: Value) -> String {
+    let request = Request::post("/challenge")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap();
+    let (status, body) = send(app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let ciphertext = URL_SAFE
+        .decode(body["ciphertext"].as_str().unwrap())
+        .unwrap();
+    let decryptor = Decryptor::new(&ciphertext[..]).unwrap();
+    let mut reader = decryptor.decrypt(std::iter::once(identity)).unwrap();
+    let mut jwt = String::new();
+    std::io::Read::read_to_string(&mut reader, &mut jwt).unwrap();
+    jwt
+}
+
+async fn retrieve(app: &Router, jwt: &str, cursor: Option<&str>) -> (StatusCode, Value) {
+    let uri = match cursor {
+        Some(c) => format!("/retrieve?cursor={}", c),
+        None => "/retrieve".to_string(),
+    };
+    let request = Request::post(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(app, request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+async fn download(app: &Router, jwt: &str, item_id: &str) -> (StatusCode, Bytes) {
+    let request = Request::get(format!("/download/{}", item_id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    send(app, request).await
+}
+
+#[tokio::test]
+async fn test_upload_retrieve_download_roundtrip() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+
+    assert_eq!(
+        upload(&app, &pubkey, b"ciphertext").await,
+        StatusCode::CREATED
+    );
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (status, body) = retrieve(&app, &jwt, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert!(body["next_cursor"].is_null());
+
+    let (status, bytes) = download(&app, &jwt, items[0].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&bytes[..], b"ciphertext");
+}
+
+#[tokio::test]
+async fn test_base64_and_bech32_keys_share_a_mailbox() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let wrapped = STANDARD.encode(&pubkey);
+
+    // The bash client sends base64("age1...")
+    assert_eq!(upload(&app, &wrapped, b"first").await, StatusCode::CREATED);
+    assert_eq!(upload(&app, &pubkey, b"second").await, StatusCode::CREATED);
+
+    // Either spelling in the challenge yields a token for the same mailbox
+    for key in [&pubkey, &wrapped] {
+        let payload = json!({ "pubkey": key, "scope": "retrieve" });
+        let jwt = decrypt_challenge(&app, &identity, payload).await;
+        let (status, body) = retrieve(&app, &jwt, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["items"].as_array().unwrap().len(), 2);
+    }
+
+    let request = Request::post("/challenge")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "pubkey": "not-a-key", "scope": "retrieve" }).to_string(),
+        ))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_ssh_key_mailbox() {
+    use crate::mailbox::tests::{TEST_SSH_ED25519_PUB, ssh_identity};
+    let app = create_router(test_state(test_config()));
+    assert_eq!(
+        upload(&app, TEST_SSH_ED25519_PUB, b"ciphertext").await,
+        StatusCode::CREATED
+    );
+
+    // Proof of possession with the SSH private key
+    let payload = json!({ "pubkey": TEST_SSH_ED25519_PUB, "scope": "retrieve" });
+    let jwt = decrypt_challenge(&app, &ssh_identity(), payload).await;
+    let (status, body) = retrieve(&app, &jwt, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    let (status, bytes) = download(&app, &jwt, items[0].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&bytes[..], b"ciphertext");
+}
+
+#[tokio::test]
+async fn test_mailboxes_are_stored_under_their_hash() {
+    let state = test_state(test_config());
+    let app = create_router(state.clone());
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    assert_eq!(
+        upload(&app, &pubkey, b"ciphertext").await,
+        StatusCode::CREATED
+    );
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    let id = body["items"][0].as_str().unwrap().parse().unwrap();
+    let item = state.storage.get_item(id).await.unwrap().unwrap();
+    assert_eq!(item.pubkey, storage_key(&state.config, &pubkey));
+    assert_eq!(state.storage.mailbox_usage(&pubkey).await.unwrap().items, 0);
+}
+
+#[tokio::test]
+async fn test_upload_rejects_invalid_requests() {
+    let app = create_router(test_state(test_config()));
+    let pubkey = x25519::Identity::generate().to_public().to_string();
+    assert_eq!(
+        upload(&app, "not-a-key", b"ciphertext").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(upload(&app, &pubkey, b"").await, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_upload_size_limit_and_download_length() {
+    let mut config = test_config();
+    config.max_upload_bytes = 10;
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+
+    // Enforced while streaming, and up front when Content-Length is announced
+    assert_eq!(
+        upload(&app, &pubkey, b"eleven byte").await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    let request = Request::post("/upload")
+        .header("X-PubKey", &pubkey)
+        .header(header::CONTENT_LENGTH, "11")
+        .body(Body::from("eleven byte"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        upload(&app, &pubkey, b"ten bytes!").await,
+        StatusCode::CREATED
+    );
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+    let request = Request::get(format!("/download/{}", body["items"][0].as_str().unwrap()))
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+}
+
+/// Send an upload-session request with the mailbox `X-PubKey`.
+async fn upload_session_request(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    pubkey: &str,
+    offset: Option<i64>,
+    body: &'static [u8],
+) -> (StatusCode, Option<String>, Bytes) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("X-PubKey", pubkey);
+    if let Some(offset) = offset {
+        request = request.header("X-Upload-Offset", offset);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::from(body)).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let offset = response
+        .headers()
+        .get("X-Upload-Offset")
+        .map(|v| v.to_str().unwrap().to_string());
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, offset, body)
+}
+
+#[tokio::test]
+async fn test_resumable_upload_roundtrip() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let other = x25519::Identity::generate().to_public().to_string();
+
+    let (status, _, body) =
+        upload_session_request(&app, "POST", "/uploads", &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["offset"], 0);
+    let uri = format!("/uploads/{}", body["upload_id"].as_str().unwrap());
+
+    let (status, offset, _) =
+        upload_session_request(&app, "PUT", &uri, &pubkey, Some(0), b"cipher").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert_eq!(offset.as_deref(), Some("6"));
+    // A chunk that does not continue at the received offset is refused
+    let (status, offset, _) =
+        upload_session_request(&app, "PUT", &uri, &pubkey, Some(0), b"cipher").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(offset.as_deref(), Some("6"));
+    // Sessions are bound to the mailbox that created them
+    let (status, _, _) = upload_session_request(&app, "GET", &uri, &other, None, b"").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, offset, _) = upload_session_request(&app, "GET", &uri, &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(offset.as_deref(), Some("6"));
+    let (status, _, _) = upload_session_request(&app, "PUT", &uri, &pubkey, Some(6), b"text").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let finalize = format!("{}/finalize", uri);
+    let (status, _, _) = upload_session_request(&app, "POST", &finalize, &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _, _) = upload_session_request(&app, "POST", &finalize, &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    assert_eq!(body["entries"][0]["size"], 10);
+    let (status, bytes) = download(&app, &jwt, body["items"][0].as_str().unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&bytes[..], b"ciphertext");
+}
+
+#[tokio::test]
+async fn test_concurrent_chunks_at_the_same_offset() {
+    use tokio::sync::oneshot;
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let (_, _, body) = upload_session_request(&app, "POST", "/uploads", &pubkey, None, b"").await;
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let uri = format!("/uploads/{}", body["upload_id"].as_str().unwrap());
+
+    // The first PUT stalls halfway through its body...
+    let (started_tx, started_rx) = oneshot::channel();
+    let (release_tx, release_rx) = oneshot::channel::<()>();
+    let first_half = futures_util::stream::once(async move {
+        started_tx.send(()).unwrap();
+        Ok::<_, std::io::Error>(Bytes::from_static(b"first"))
+    });
+    let second_half = futures_util::stream::once(async move {
+        release_rx.await.unwrap();
+        Ok(Bytes::from_static(b"-chunk"))
+    });
+    let request = Request::put(&uri)
+        .header("X-PubKey", &pubkey)
+        .header("X-Upload-Offset", 0)
+        .body(Body::from_stream(futures_util::StreamExt::chain(
+            first_half,
+            second_half,
+        )))
+        .unwrap();
+    let first = tokio::spawn(app.clone().oneshot(request));
+    started_rx.await.unwrap();
+    // ...so a retry at the same offset cannot write over it
+    let (status, _, _) =
+        upload_session_request(&app, "PUT", &uri, &pubkey, Some(0), b"other-chunk!").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    release_tx.send(()).unwrap();
+    assert_eq!(
+        first.await.unwrap().unwrap().status(),
+        StatusCode::NO_CONTENT
+    );
+    let (status, offset, _) =
+        upload_session_request(&app, "PUT", &uri, &pubkey, Some(0), b"other-chunk!").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(offset.as_deref(), Some("11"));
+
+    let finalize = format!("{}/finalize", uri);
+    let (status, _, _) = upload_session_request(&app, "POST", &finalize, &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::CREATED);
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    let (_, bytes) = download(&app, &jwt, body["items"][0].as_str().unwrap()).await;
+    assert_eq!(&bytes[..], b"first-chunk");
+}
+
+#[tokio::test]
+async fn test_resumable_upload_enforces_size_limit() {
+    let mut config = test_config();
+    config.max_upload_bytes = 8;
+    let app = create_router(test_state(config));
+    let pubkey = x25519::Identity::generate().to_public().to_string();
+
+    let (_, _, body) = upload_session_request(&app, "POST", "/uploads", &pubkey, None, b"").await;
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let uri = format!("/uploads/{}", body["upload_id"].as_str().unwrap());
+    let (status, _, _) =
+        upload_session_request(&app, "PUT", &uri, &pubkey, Some(0), b"cipher").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _) = upload_session_request(&app, "PUT", &uri, &pubkey, Some(6), b"text").await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    // The rejected chunk is not counted
+    let (_, offset, _) = upload_session_request(&app, "GET", &uri, &pubkey, None, b"").await;
+    assert_eq!(offset.as_deref(), Some("6"));
+}
+
+/// Upload `body` for a fresh mailbox and return a /retrieve JWT and its item ID.
+async fn upload_single_item(app: &Router, body: &'static [u8]) -> (String, String) {
+    let identity = x25519::Identity::generate();
+    upload(app, &identity.to_public().to_string(), body).await;
+    let jwt = obtain_jwt(app, &identity, "retrieve", None).await;
+    let (_, listing) = retrieve(app, &jwt, None).await;
+    (jwt, listing["items"][0].as_str().unwrap().to_string())
+}
+
+async fn download_with(
+    app: &Router,
+    method: &str,
+    jwt: &str,
+    item_id: &str,
+    headers: &[(header::HeaderName, &str)],
+) -> axum::response::Response {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("/download/{}", item_id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt));
+    for (name, value) in headers {
+        request = request.header(name, *value);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_download_ranges() {
+    let app = create_router(test_state(test_config()));
+    let (jwt, item_id) = upload_single_item(&app, b"ciphertext").await;
+
+    for (range, expected, content_range) in [
+        ("bytes=2-5", &b"pher"[..], "bytes 2-5/10"),
+        ("bytes=6-", b"text", "bytes 6-9/10"),
+        ("bytes=-3", b"ext", "bytes 7-9/10"),
+        ("bytes=8-100", b"xt", "bytes 8-9/10"),
+    ] {
+        let response = download_with(&app, "GET", &jwt, &item_id, &[(header::RANGE, range)]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], content_range);
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            expected.len().to_string()
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], expected);
+    }
+
+    let response =
+        download_with(&app, "GET", &jwt, &item_id, &[(header::RANGE, "bytes=10-")]).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+    // Multiple ranges are not supported and fall back to the full body
+    let response = download_with(
+        &app,
+        "GET",
+        &jwt,
+        &item_id,
+        &[(header::RANGE, "bytes=0-1,4-5")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_download_head_and_conditional_requests() {
+    let app = create_router(test_state(test_config()));
+    let (jwt, item_id) = upload_single_item(&app, b"ciphertext").await;
+
+    let response = download_with(&app, "HEAD", &jwt, &item_id, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        etag,
+        format!("\"{}\"", crate::blob::content_key(b"ciphertext"))
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(body.is_empty());
+
+    let response = download_with(
+        &app,
+        "GET",
+        &jwt,
+        &item_id,
+        &[(header::IF_NONE_MATCH, &etag)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let response = download_with(
+        &app,
+        "GET",
+        &jwt,
+        &item_id,
+        &[(header::IF_NONE_MATCH, "\"something-else\"")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // If-Range only honours the Range header while the validator still matches
+    let response = download_with(
+        &app,
+        "GET",
+        &jwt,
+        &item_id,
+        &[(header::RANGE, "bytes=0-1"), (header::IF_RANGE, &etag)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let response = download_with(
+        &app,
+        "GET",
+        &jwt,
+        &item_id,
+        &[
+            (header::RANGE, "bytes=0-1"),
+            (header::IF_RANGE, "\"stale\""),
+        ],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+async fn delete_item(app: &Router, jwt: &str, item_id: &str) -> StatusCode {
+    let request = Request::delete(format!("/items/{}", item_id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    send(app, request).await.0
+}
+
+#[tokio::test]
+async fn test_delete_item_requires_owner_and_delete_scope() {
+    let app = create_router(test_state(test_config()));
+    let owner = x25519::Identity::generate();
+    upload(&app, &owner.to_public().to_string(), b"ciphertext").await;
+    let retrieve_jwt = obtain_jwt(&app, &owner, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &retrieve_jwt, None).await;
+    let item_id = listing["items"][0].as_str().unwrap().to_string();
+
+    // A /retrieve token is not enough, and other mailboxes cannot see the item
+    assert_eq!(
+        delete_item(&app, &retrieve_jwt, &item_id).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let intruder = x25519::Identity::generate();
+    let intruder_jwt = obtain_jwt(&app, &intruder, "delete", None).await;
+    assert_eq!(
+        delete_item(&app, &intruder_jwt, &item_id).await,
+        StatusCode::NOT_FOUND
+    );
+
+    let delete_jwt = obtain_jwt(&app, &owner, "delete", None).await;
+    assert_eq!(
+        delete_item(&app, &delete_jwt, &item_id).await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        delete_item(&app, &delete_jwt, &item_id).await,
+        StatusCode::NOT_FOUND
+    );
+    let (_, listing) = retrieve(&app, &retrieve_jwt, None).await;
+    assert!(listing["items"].as_array().unwrap().is_empty());
+    let (status, _) = download(&app, &retrieve_jwt, &item_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_bulk_delete_keeps_shared_blobs() {
+    let state = test_state(test_config());
+    let app = create_router(state.clone());
+    let owner = x25519::Identity::generate();
+    let pubkey = owner.to_public().to_string();
+    upload(&app, &pubkey, b"same").await;
+    upload(&app, &pubkey, b"unique").await;
+    // Another mailbox stores identical ciphertext, sharing the blob
+    let (other_jwt, other_item) = upload_single_item(&app, b"same").await;
+
+    let retrieve_jwt = obtain_jwt(&app, &owner, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &retrieve_jwt, None).await;
+    let mut ids: Vec<Value> = listing["items"].as_array().unwrap().clone();
+    ids.push(json!(other_item));
+    let delete_jwt = obtain_jwt(&app, &owner, "delete", None).await;
+    let request = Request::delete("/items")
+        .header(header::AUTHORIZATION, format!("Bearer {}", delete_jwt))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "ids": ids }).to_string()))
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    // Only the caller's own items are deleted
+    assert_eq!(body["deleted"].as_array().unwrap().len(), 2);
+
+    let (status, bytes) = download(&app, &other_jwt, &other_item).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&bytes[..], b"same");
+    let unique = crate::blob::content_key(b"unique");
+    assert!(state.blobs.open(&unique).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_upload_expiry_header() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    for (value, expected) in [
+        ("0", StatusCode::BAD_REQUEST),
+        ("soon", StatusCode::BAD_REQUEST),
+        ("3600", StatusCode::CREATED),
+    ] {
+        let request = Request::post("/upload")
+            .header("X-PubKey", &pubkey)
+            .header("X-Expires-In", value)
+            .body(Body::from("ciphertext"))
+            .unwrap();
+        assert_eq!(send(&app, request).await.0, expected);
+    }
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    let expires_at: chrono::DateTime<chrono::Utc> =
+        serde_json::from_value(body["entries"][0]["expires_at"].clone()).unwrap();
+    assert!(expires_at > chrono::Utc::now() + chrono::Duration::seconds(3500));
+}
+
+#[tokio::test]
+async fn test_read_once_item_is_downloaded_exactly_once() {
+    let state = test_state(test_config());
+    let app = create_router(state.clone());
+    let identity = x25519::Identity::generate();
+    let request = Request::post("/upload")
+        .header("X-PubKey", identity.to_public().to_string())
+        .header("X-Read-Once", "true")
+        .body(Body::from("credentials"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::CREATED);
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    assert_eq!(listing["entries"][0]["read_once"], true);
+    let item_id = listing["items"][0].as_str().unwrap().to_string();
+
+    // HEAD and Range requests do not consume the item
+    let response = download_with(&app, "HEAD", &jwt, &item_id, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (first, second) = tokio::join!(
+        download_with(&app, "GET", &jwt, &item_id, &[(header::RANGE, "bytes=0-1")]),
+        download_with(&app, "GET", &jwt, &item_id, &[]),
+    );
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::NOT_FOUND]);
+    let winner = if first.status() == StatusCode::OK {
+        first
+    } else {
+        second
+    };
+    let body = axum::body::to_bytes(winner.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"credentials");
+
+    let (status, _) = download(&app, &jwt, &item_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    assert!(listing["items"].as_array().unwrap().is_empty());
+    // The blob goes away once the response body has been dropped
+    tokio::task::yield_now().await;
+    let key = crate::blob::content_key(b"credentials");
+    assert!(state.blobs.open(&key).await.unwrap().is_none());
+}
+
+/// A blob store whose reads fail while `failing` is set.
+struct FlakyBlobStore {
+    inner: MemoryBlobStore,
+    failing: std::sync::atomic::AtomicBool,
+}
+
+impl FlakyBlobStore {
+    fn check(&self) -> Result<(), crate::error::BlobError> {
+        if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(crate::error::BlobError::Http("unavailable".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::blob::BlobStore for FlakyBlobStore {
+    async fn put(&self, data: &[u8]) -> Result<String, crate::error::BlobError> {
+        self.inner.put(data).await
+    }
+
+    async fn put_staged(
+        &self,
+        staged: crate::blob::StagedBlob,
+    ) -> Result<String, crate::error::BlobError> {
+        self.inner.put_staged(staged).await
+    }
+
+    async fn open(
+        &self,
+        key: &str,
+    ) -> Result<Option<crate::blob::BlobReader>, crate::error::BlobError> {
+        self.check()?;
+        self.inner.open(key).await
+    }
+
+    async fn open_range(
+        &self,
+        key: &str,
+        range: std::ops::Range<u64>,
+    ) -> Result<Option<crate::blob::BlobReader>, crate::error::BlobError> {
+        self.check()?;
+        self.inner.open_range(key, range).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), crate::error::BlobError> {
+        self.inner.delete(key).await
+    }
+}
+
+#[tokio::test]
+async fn test_read_once_item_survives_a_blob_store_failure() {
+    let blobs = Arc::new(FlakyBlobStore {
+        inner: MemoryBlobStore::new(),
+        failing: false.into(),
+    });
+    let state = AppState {
+        blobs: blobs.clone(),
+        ..test_state(test_config())
+    };
+    let app = create_router(state);
+    let identity = x25519::Identity::generate();
+    let request = Request::post("/upload")
+        .header("X-PubKey", identity.to_public().to_string())
+        .header("X-Read-Once", "true")
+        .body(Body::from("credentials"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::CREATED);
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    let item_id = listing["items"][0].as_str().unwrap().to_string();
+
+    blobs
+        .failing
+        .store(true, std::sync::atomic::Ordering::SeqCst);
+    let (status, _) = download(&app, &jwt, &item_id).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    // The failed attempt neither consumed the item nor released its blob
+    blobs
+        .failing
+        .store(false, std::sync::atomic::Ordering::SeqCst);
+    let (status, body) = download(&app, &jwt, &item_id).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[..], b"credentials");
+    let (status, _) = download(&app, &jwt, &item_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_retrieve_paginates_with_cursor() {
+    let mut config = test_config();
+    config.retrieve_page_size = 2;
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    for _ in 0..3 {
+        assert_eq!(
+            upload(&app, &pubkey, b"ciphertext").await,
+            StatusCode::CREATED
+        );
+    }
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, first) = retrieve(&app, &jwt, None).await;
+    assert_eq!(first["items"].as_array().unwrap().len(), 2);
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let (status, second) = retrieve(&app, &jwt, Some(cursor)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(second["items"].as_array().unwrap().len(), 1);
+    assert!(
+        !first["items"]
+            .as_array()
+            .unwrap()
+            .contains(&second["items"][0])
+    );
+
+    let (status, _) = retrieve(&app, &jwt, Some("garbage")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Another mailbox cannot page through with this cursor
+    let intruder = x25519::Identity::generate();
+    let intruder_jwt = obtain_jwt(&app, &intruder, "retrieve", None).await;
+    let (status, _) = retrieve(&app, &intruder_jwt, Some(cursor)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_download_other_mailbox_is_not_found() {
+    let app = create_router(test_state(test_config()));
+    let owner = x25519::Identity::generate();
+    let intruder = x25519::Identity::generate();
+    upload(&app, &owner.to_public().to_string(), b"secret").await;
+
+    let owner_jwt = obtain_jwt(&app, &owner, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &owner_jwt, None).await;
+    let item_id = body["items"][0].as_str().unwrap().to_string();
+
+    let intruder_jwt = obtain_jwt(&app, &intruder, "retrieve", None).await;
+    let (status, _) = download(&app, &intruder_jwt, &item_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_notify_registration_triggers_telegram_on_upload() {
+    let (base_url, mut rx) = start_mock_bot_api().await;
+    let mut config = test_config();
+    config.telegram_bot_token = Some("123:TESTTOKEN".to_string());
+    config.telegram_api_base_url = base_url;
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+
+    // A retrieve-scoped token must not be accepted by /notify
+    let retrieve_jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let request = Request::post("/notify")
+        .header(header::AUTHORIZATION, format!("Bearer {}", retrieve_jwt))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::UNAUTHORIZED);
+
+    let jwt = obtain_jwt(&app, &identity, "notify", Some("@recipient")).await;
+    let request = Request::post("/notify")
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::OK);
+
+    let pubkey = identity.to_public().to_string();
+    assert_eq!(
+        upload(&app, &pubkey, b"ciphertext").await,
+        StatusCode::CREATED
+    );
+    let (_, body) = rx.recv().await.unwrap();
+    assert_eq!(body["chat_id"], "@recipient");
+}
+
+#[tokio::test]
+async fn test_retrieve_lists_metadata_and_labels() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let request = Request::post("/upload")
+        .header("X-PubKey", &pubkey)
+        .header("X-Labels", "invoice, urgent")
+        .body(Body::from("ciphertext"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::CREATED);
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    let entry = &body["entries"][0];
+    assert_eq!(entry["id"], body["items"][0]);
+    assert_eq!(entry["size"], 10);
+    assert_eq!(entry["labels"], json!(["invoice", "urgent"]));
+
+    let too_many = ["x"; 9].join(",");
+    let request = Request::post("/upload")
+        .header("X-PubKey", &pubkey)
+        .header("X-Labels", too_many)
+        .body(Body::from("ciphertext"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::BAD_REQUEST);
+}
+
+async fn ack(app: &Router, jwt: &str, ids: &[&str]) -> (StatusCode, Value) {
+    let request = Request::post("/ack")
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "ids": ids }).to_string()))
+        .unwrap();
+    let (status, body) = send(app, request).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_ack_marks_items_delivered() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    upload(&app, &pubkey, b"older").await;
+    upload(&app, &pubkey, b"newer").await;
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    let newer = listing["items"][0].as_str().unwrap().to_string();
+    let older = listing["items"][1].as_str().unwrap().to_string();
+
+    // Other mailboxes' items and unknown IDs are skipped
+    let (other_jwt, other_item) = upload_single_item(&app, b"other").await;
+    let (status, body) = ack(&app, &jwt, &[&newer, &other_item]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["acknowledged"], json!([newer]));
+    assert!(body["purge_at"].is_string());
+    let (status, _) = ack(&app, &jwt, &["not-a-uuid"]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let delete_jwt = obtain_jwt(&app, &identity, "delete", None).await;
+    let (status, _) = ack(&app, &delete_jwt, &[&older]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    for (state, expected) in [
+        ("delivered", vec![newer.clone()]),
+        ("undelivered", vec![older.clone()]),
+        ("all", vec![newer.clone(), older.clone()]),
+    ] {
+        let request = Request::post(format!("/retrieve?state={}", state))
+            .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["items"], json!(expected), "state={}", state);
+    }
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    assert!(listing["entries"][0]["delivered_at"].is_string());
+    assert!(listing["entries"][1].get("delivered_at").is_none());
+
+    // Acknowledged items stay downloadable during the grace period
+    let (status, bytes) = download(&app, &jwt, &newer).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&bytes[..], b"newer");
+    let (status, _) = download(&app, &other_jwt, &other_item).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_ack_without_grace_period_purges_item() {
+    let mut config = test_config();
+    config.ack_grace_period_seconds = 0;
+    let state = test_state(config);
+    let app = create_router(state.clone());
+    let (jwt, item_id) = upload_single_item(&app, b"acked").await;
+    let (status, body) = ack(&app, &jwt, &[&item_id]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["acknowledged"], json!([item_id]));
+
+    let (status, _) = download(&app, &jwt, &item_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(crate::retention::purge_expired(&state).await.unwrap(), 1);
+    let key = crate::blob::content_key(b"acked");
+    assert!(state.blobs.open(&key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_mailbox_quota() {
+    let mut config = test_config();
+    config.mailbox_max_items = Some(2);
+    config.mailbox_max_bytes = Some(10);
+    let state = test_state(config);
+    let app = create_router(state.clone());
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let upload_with_headers = |body: &'static [u8]| {
+        let app = app.clone();
+        let request = Request::post("/upload")
+            .header("X-PubKey", &pubkey)
+            .body(Body::from(body))
+            .unwrap();
+        async move { app.oneshot(request).await.unwrap() }
+    };
+
+    let response = upload_with_headers(b"abcd").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()["X-Quota-Remaining-Items"], "1");
+    assert_eq!(response.headers()["X-Quota-Remaining-Bytes"], "6");
+    // More than the whole byte quota can never fit
+    assert_eq!(
+        upload(&app, &pubkey, b"0123456789a").await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    // Fits the limit but not the remaining space; its blob is released again
+    let response = upload_with_headers(b"efghijkl").await;
+    assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+    assert_eq!(response.headers()["X-Quota-Remaining-Bytes"], "6");
+    let rejected = crate::blob::content_key(b"efghijkl");
+    assert!(state.blobs.open(&rejected).await.unwrap().is_none());
+
+    let response = upload_with_headers(b"xyz").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()["X-Quota-Remaining-Items"], "0");
+    assert_eq!(
+        upload(&app, &pubkey, b"z").await,
+        StatusCode::INSUFFICIENT_STORAGE
+    );
+    // Other mailboxes are unaffected
+    let other = x25519::Identity::generate().to_public().to_string();
+    assert_eq!(upload(&app, &other, b"abcd").await, StatusCode::CREATED);
+
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let request = Request::get("/usage")
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({ "items": 2, "bytes": 7, "max_items": 2, "max_bytes": 10 })
+    );
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_mailbox() {
+    let mut config = test_config();
+    config.rate_limit_retrieve = Some(crate::ratelimit::RateLimit::new(2, 60));
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    for _ in 0..2 {
+        assert_eq!(retrieve(&app, &jwt, None).await.0, StatusCode::OK);
+    }
+    let request = Request::post("/retrieve")
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=30).contains(&retry_after));
+    // Other mailboxes and route groups have their own allowance
+    let other = x25519::Identity::generate();
+    let other_jwt = obtain_jwt(&app, &other, "retrieve", None).await;
+    assert_eq!(retrieve(&app, &other_jwt, None).await.0, StatusCode::OK);
+    let pubkey = identity.to_public().to_string();
+    assert_eq!(upload(&app, &pubkey, b"data").await, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_strangers_cannot_drain_a_mailbox_allowance() {
+    let mut config = test_config();
+    config.rate_limit_challenge = Some(crate::ratelimit::RateLimit::new(1, 60));
+    config.rate_limit_upload = Some(crate::ratelimit::RateLimit::new(1, 60));
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let from_peer = |mut request: Request<Body>, peer: u8| {
+        let addr: std::net::SocketAddr = format!("192.0.2.{}:5000", peer).parse().unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(addr));
+        request
+    };
+    // Requests naming the mailbox from many addresses only count per address
+    for peer in 1..=3 {
+        let challenge = Request::post("/challenge")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "pubkey": pubkey, "scope": "retrieve" }).to_string(),
+            ))
+            .unwrap();
+        assert_eq!(
+            send(&app, from_peer(challenge, peer)).await.0,
+            StatusCode::OK
+        );
+        let upload = Request::post("/upload")
+            .header("X-PubKey", &pubkey)
+            .body(Body::from("data"))
+            .unwrap();
+        assert_eq!(
+            send(&app, from_peer(upload, peer)).await.0,
+            StatusCode::CREATED
+        );
+    }
+    // The owner still gets a token and reads the mailbox
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (status, listing) = retrieve(&app, &jwt, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(listing["items"].as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_ip() {
+    let mut config = test_config();
+    config.rate_limit_upload = Some(crate::ratelimit::RateLimit::new(1, 60));
+    let app = create_router(test_state(config));
+    let peer: std::net::SocketAddr = "192.0.2.1:5000".parse().unwrap();
+    let upload_from = |peer: std::net::SocketAddr| {
+        let pubkey = x25519::Identity::generate().to_public().to_string();
+        let mut request = Request::post("/upload")
+            .header("X-PubKey", pubkey)
+            .body(Body::from("data"))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(peer));
+        request
+    };
+    assert_eq!(send(&app, upload_from(peer)).await.0, StatusCode::CREATED);
+    assert_eq!(
+        send(&app, upload_from(peer)).await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    let other_peer = "192.0.2.2:5000".parse().unwrap();
+    assert_eq!(
+        send(&app, upload_from(other_peer)).await.0,
+        StatusCode::CREATED
+    );
+}
+
+async fn pow_challenge(app: &Router, pubkey: &str) -> (String, u64) {
+    let request = Request::post("/pow")
+        .header("X-PubKey", pubkey)
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    (
+        body["challenge"].as_str().unwrap().to_string(),
+        body["difficulty"].as_u64().unwrap(),
+    )
+}
+
+async fn upload_with_pow(
+    app: &Router,
+    pubkey: &str,
+    stamp: Option<&str>,
+) -> axum::response::Response {
+    let mut request = Request::post("/upload").header("X-PubKey", pubkey);
+    if let Some(stamp) = stamp {
+        request = request.header("X-PoW", stamp);
+    }
+    let request = request.body(Body::from("data")).unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_upload_requires_proof_of_work() {
+    let mut config = test_config();
+    config.pow_difficulty = 8;
+    let app = create_router(test_state(config));
+    let pubkey = x25519::Identity::generate().to_public().to_string();
+
+    let response = upload_with_pow(&app, &pubkey, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.headers()["X-PoW-Difficulty"], "8");
+    let (challenge, difficulty) = pow_challenge(&app, &pubkey).await;
+    assert_eq!(difficulty, 8);
+    let stamp = crate::pow::tests::solve(&challenge, 8);
+    let response = upload_with_pow(&app, &pubkey, Some(&stamp)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    // A stamp pays for one upload only
+    let response = upload_with_pow(&app, &pubkey, Some(&stamp)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.headers()["X-PoW-Difficulty"], "8");
+
+    // Stamps are bound to their mailbox
+    let other = x25519::Identity::generate().to_public().to_string();
+    let response = upload_with_pow(&app, &other, Some(&stamp)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // Resumable uploads need a stamp to start a session
+    let request = Request::post("/uploads")
+        .header("X-PubKey", &other)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_mailbox_raises_proof_of_work_difficulty() {
+    let mut config = test_config();
+    config.pow_max_difficulty = 12;
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    // No PoW by default
+    assert_eq!(
+        upload_with_pow(&app, &pubkey, None).await.status(),
+        StatusCode::CREATED
+    );
+    let (old_challenge, difficulty) = pow_challenge(&app, &pubkey).await;
+    assert_eq!(difficulty, 0);
+
+    let jwt = obtain_jwt(&app, &identity, "settings", None).await;
+    let put_settings = |body: Value| {
+        Request::put("/settings")
+            .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let (status, _) = send(&app, put_settings(json!({ "pow_difficulty": 13 }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, put_settings(json!({ "pow_difficulty": 6 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let request = Request::get("/settings")
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    let (_, body) = send(&app, request).await;
+    assert_eq!(
+        serde_json::from_slice::<Value>(&body).unwrap(),
+        json!({ "pow_difficulty": 6, "capability_required": false })
+    );
+
+    assert_eq!(
+        upload_with_pow(&app, &pubkey, None).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    // Challenges issued before the change are too weak now
+    let stale = crate::pow::tests::solve(&old_challenge, 0);
+    assert_eq!(
+        upload_with_pow(&app, &pubkey, Some(&stale)).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    let (challenge, difficulty) = pow_challenge(&app, &pubkey).await;
+    assert_eq!(difficulty, 6);
+    let stamp = crate::pow::tests::solve(&challenge, 6);
+    assert_eq!(
+        upload_with_pow(&app, &pubkey, Some(&stamp)).await.status(),
+        StatusCode::CREATED
+    );
+}
+
+#[tokio::test]
+async fn test_upload_capabilities() {
+    let mut config = test_config();
+    config.pow_difficulty = 8;
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let jwt = obtain_jwt(&app, &identity, "settings", None).await;
+    let request = Request::put("/settings")
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            json!({ "capability_required": true }).to_string(),
+        ))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::OK);
+
+    // Even a valid PoW stamp is not enough now
+    let (challenge, _) = pow_challenge(&app, &pubkey).await;
+    let stamp = crate::pow::tests::solve(&challenge, 8);
+    assert_eq!(
+        upload_with_pow(&app, &pubkey, Some(&stamp)).await.status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let payload = json!({
+        "pubkey": pubkey,
+        "scope": "capability",
+        "max_uploads": 2,
+        "max_bytes": 8,
+    });
+    let capability = decrypt_challenge(&app, &identity, payload).await;
+    let upload = |body: &'static str| {
+        Request::post("/upload")
+            .header("X-PubKey", &pubkey)
+            .header("X-Capability", &capability)
+            .body(Body::from(body))
+            .unwrap()
+    };
+    // The capability replaces the PoW stamp
+    assert_eq!(send(&app, upload("data")).await.0, StatusCode::CREATED);
+    // Rejected uploads do not count as a use
+    assert_eq!(
+        send(&app, upload("too large")).await.0,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert_eq!(send(&app, upload("")).await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(send(&app, upload("data")).await.0, StatusCode::CREATED);
+    assert_eq!(send(&app, upload("data")).await.0, StatusCode::FORBIDDEN);
+
+    // Capabilities are bound to their mailbox and never authenticate the owner
+    let other = x25519::Identity::generate().to_public().to_string();
+    let request = Request::post("/upload")
+        .header("X-PubKey", &other)
+        .header("X-Capability", &capability)
+        .body(Body::from("data"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::FORBIDDEN);
+    let (status, _) = retrieve(&app, &capability, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_resumable_upload_with_capability() {
+    let app = create_router(test_state(test_config()));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let payload = json!({ "pubkey": pubkey, "scope": "capability", "max_bytes": 4 });
+    let capability = decrypt_challenge(&app, &identity, payload).await;
+    let create = || async {
+        let request = Request::post("/uploads")
+            .header("X-PubKey", &pubkey)
+            .header("X-Capability", &capability)
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        body["upload_id"].as_str().unwrap().to_string()
+    };
+    let put_chunk = |upload_id: String, chunk: &'static str| {
+        Request::put(format!("/uploads/{}", upload_id))
+            .header("X-PubKey", &pubkey)
+            .header("X-Upload-Offset", "0")
+            .body(Body::from(chunk))
+            .unwrap()
+    };
+    let finalize = |upload_id: String| {
+        Request::post(format!("/uploads/{}/finalize", upload_id))
+            .header("X-PubKey", &pubkey)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let first = create().await;
+    assert_eq!(
+        send(&app, put_chunk(first.clone(), "too large")).await.0,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    // Starting a session does not spend the single use, finalizing does
+    let second = create().await;
+    assert_eq!(
+        send(&app, put_chunk(first.clone(), "data")).await.0,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        send(&app, put_chunk(second.clone(), "data")).await.0,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(send(&app, finalize(first)).await.0, StatusCode::CREATED);
+    assert_eq!(send(&app, finalize(second)).await.0, StatusCode::FORBIDDEN);
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    assert_eq!(listing["items"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_capability_use_is_returned_when_the_mailbox_is_full() {
+    let mut config = test_config();
+    config.mailbox_max_items = Some(1);
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let payload = json!({ "pubkey": pubkey, "scope": "capability" });
+    let capability = decrypt_challenge(&app, &identity, payload).await;
+    let request = Request::post("/uploads")
+        .header("X-PubKey", &pubkey)
+        .header("X-Capability", &capability)
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let upload_id = body["upload_id"].as_str().unwrap();
+    let request = Request::put(format!("/uploads/{}", upload_id))
+        .header("X-PubKey", &pubkey)
+        .header("X-Upload-Offset", "0")
+        .body(Body::from("data"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::NO_CONTENT);
+    // The mailbox fills up before the session is finalized
+    assert_eq!(upload(&app, &pubkey, b"other").await, StatusCode::CREATED);
+    let request = Request::post(format!("/uploads/{}/finalize", upload_id))
+        .header("X-PubKey", &pubkey)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        send(&app, request).await.0,
+        StatusCode::INSUFFICIENT_STORAGE
+    );
+
+    // Once there is room again, the capability still has its single use
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, listing) = retrieve(&app, &jwt, None).await;
+    let item_id = listing["items"][0].as_str().unwrap();
+    let jwt = obtain_jwt(&app, &identity, "delete", None).await;
+    let request = Request::delete(format!("/items/{}", item_id))
+        .header(header::AUTHORIZATION, format!("Bearer {}", jwt))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::NO_CONTENT);
+    let request = Request::post("/upload")
+        .header("X-PubKey", &pubkey)
+        .header("X-Capability", &capability)
+        .body(Body::from("data"))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_eddsa_tokens_verify_against_jwks() {
+    let key_file = crate::keyring::tests::ed25519_key_file(3);
+    let mut config = test_config();
+    config.jwt_algorithm = "EdDSA".to_string();
+    config.jwt_ed25519_key_file = Some(key_file.to_string_lossy().into_owned());
+    config.keyring = crate::keyring::Keyring::from_config(&config).unwrap();
+    std::fs::remove_file(&key_file).unwrap();
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (status, _) = retrieve(&app, &jwt, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // An edge proxy needs nothing but the published keys to check the token
+    let request = Request::get("/.well-known/jwks.json")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let jwks: jsonwebtoken::jwk::JwkSet = serde_json::from_slice(&body).unwrap();
+    let kid = jsonwebtoken::decode_header(&jwt).unwrap().kid.unwrap();
+    let jwk = jwks.find(&kid).unwrap();
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::EdDSA);
+    validation.set_audience(&["/retrieve"]);
+    let key = jsonwebtoken::DecodingKey::from_jwk(jwk).unwrap();
+    let claims = jsonwebtoken::decode::<Value>(&jwt, &key, &validation).unwrap();
+    assert_eq!(claims.claims["sub"], identity.to_public().to_string());
+}
+
+#[tokio::test]
+async fn test_age_upload_validation() {
+    use crate::agefile::tests::encrypt;
+    let mut config = test_config();
+    config.validate_age_uploads = true;
+    let app = create_router(test_state(config));
+    let identity = x25519::Identity::generate();
+    let pubkey = identity.to_public().to_string();
+    let upload_body = |body: Vec<u8>| {
+        Request::post("/upload")
+            .header("X-PubKey", &pubkey)
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let (status, body) = send(&app, upload_body(b"plaintext".to_vec())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(&body[..], b"Body is not an age v1 file");
+    // Encrypted, but not to a key type that can be this mailbox
+    let ssh = crate::mailbox::tests::TEST_SSH_ED25519_PUB
+        .parse::<age::ssh::Recipient>()
+        .unwrap();
+    let (status, body) = send(&app, upload_body(encrypt(&[&ssh], b"hi", false))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        &body[..],
+        b"Body is not encrypted to an age X25519 recipient"
+    );
+
+    let recipient = identity.to_public();
+    for armored in [false, true] {
+        let ciphertext = encrypt(&[&recipient], b"hi", armored);
+        assert_eq!(
+            send(&app, upload_body(ciphertext)).await.0,
+            StatusCode::CREATED
+        );
+    }
+    let jwt = obtain_jwt(&app, &identity, "retrieve", None).await;
+    let (_, body) = retrieve(&app, &jwt, None).await;
+    let mut formats: Vec<_> = body["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["format"].as_str().unwrap())
+        .collect();
+    formats.sort();
+    assert_eq!(formats, ["armored", "binary"]);
+
+    // Resumable uploads are checked when finalized, and discarded if rejected
+    let (_, _, body) = upload_session_request(&app, "POST", "/uploads", &pubkey, None, b"").await;
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let uri = format!("/uploads/{}", body["upload_id"].as_str().unwrap());
+    let (status, _, _) =
+        upload_session_request(&app, "PUT", &uri, &pubkey, Some(0), b"plaintext").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let finalize = format!("{}/finalize", uri);
+    let (status, _, _) = upload_session_request(&app, "POST", &finalize, &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, _) = upload_session_request(&app, "GET", &uri, &pubkey, None, b"").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
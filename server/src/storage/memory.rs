@@ -0,0 +1,385 @@
+use super::{DeliveryFilter, PageKey, Quota, Storage, Usage};
+use crate::agefile::AgeFormat;
+use crate::db::{
+    DbItem, DbNotification, DbUploadSession, ItemMeta, MailboxSettings, NewItem, NewUploadSession,
+};
+use crate::error::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+struct MemoryItem {
+    item: DbItem,
+    labels: Option<Vec<String>>,
+    expires_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+    format: Option<AgeFormat>,
+}
+
+impl MemoryItem {
+    fn meta(&self) -> ItemMeta {
+        ItemMeta {
+            id: self.item.id,
+            created_at: self.item.created_at,
+            size: self.item.size,
+            labels: self.labels.clone().map(Json),
+            expires_at: self.expires_at,
+            read_once: self.item.read_once,
+            delivered_at: self.delivered_at,
+            format: self.format.map(|format| format.as_str().to_string()),
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+fn usage(items: &HashMap<Uuid, MemoryItem>, pubkey: &str) -> Usage {
+    let now = Utc::now();
+    items
+        .values()
+        .filter(|stored| stored.item.pubkey == pubkey && !stored.is_expired(now))
+        .fold(Usage::default(), |usage, stored| Usage {
+            items: usage.items + 1,
+            bytes: usage.bytes + stored.item.size,
+        })
+}
+
+/// Non-persistent storage backend keeping everything in process memory.
+/// Maps keyed by `String` use the mailbox storage key (hex HMAC), like the
+/// `pubkey` columns of the SQL backends.
+#[derive(Default)]
+pub struct MemoryStorage {
+    items: Mutex<HashMap<Uuid, MemoryItem>>,
+    notifications: Mutex<HashMap<String, DbNotification>>,
+    mailbox_settings: Mutex<HashMap<String, MailboxSettings>>,
+    capability_uses: Mutex<HashMap<Uuid, (i64, DateTime<Utc>)>>, // uses, expires_at
+    upload_sessions: Mutex<HashMap<Uuid, DbUploadSession>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn insert_item(&self, new_item: &NewItem, quota: Quota) -> Result<DbItem, StorageError> {
+        let mut items = self.items.lock().unwrap();
+        if !quota.allows(usage(&items, &new_item.pubkey), new_item.size) {
+            return Err(StorageError::QuotaExceeded);
+        }
+        let item = DbItem {
+            id: Uuid::new_v4(),
+            pubkey: new_item.pubkey.clone(),
+            blob_key: new_item.blob_key.clone(),
+            size: new_item.size,
+            read_once: new_item.read_once,
+            created_at: Utc::now(),
+        };
+        items.insert(
+            item.id,
+            MemoryItem {
+                item: item.clone(),
+                labels: new_item.labels.clone(),
+                expires_at: new_item.expires_at,
+                delivered_at: None,
+                format: new_item.format,
+            },
+        );
+        Ok(item)
+    }
+
+    async fn mailbox_usage(&self, pubkey: &str) -> Result<Usage, StorageError> {
+        Ok(usage(&self.items.lock().unwrap(), pubkey))
+    }
+
+    async fn list_items(
+        &self,
+        pubkey: &str,
+        filter: DeliveryFilter,
+        after: Option<PageKey>,
+        limit: u32,
+    ) -> Result<Vec<ItemMeta>, StorageError> {
+        let now = Utc::now();
+        let items = self.items.lock().unwrap();
+        let mut page: Vec<ItemMeta> = items
+            .values()
+            .filter(|stored| {
+                stored.item.pubkey == pubkey
+                    && !stored.is_expired(now)
+                    && filter.matches(stored.delivered_at)
+            })
+            .map(MemoryItem::meta)
+            .filter(|meta| match after {
+                Some(key) => (meta.created_at, meta.id) < key,
+                None => true,
+            })
+            .collect();
+        page.sort_by_key(|meta| Reverse((meta.created_at, meta.id)));
+        page.truncate(limit as usize);
+        Ok(page)
+    }
+
+    async fn get_item(&self, id: Uuid) -> Result<Option<DbItem>, StorageError> {
+        Ok(self
+            .items
+            .lock()
+            .unwrap()
+            .get(&id)
+            .filter(|stored| !stored.is_expired(Utc::now()))
+            .map(|stored| stored.item.clone()))
+    }
+
+    async fn delete_item(&self, id: Uuid) -> Result<bool, StorageError> {
+        Ok(self.items.lock().unwrap().remove(&id).is_some())
+    }
+
+    async fn count_blob_refs(&self, blob_key: &str) -> Result<i64, StorageError> {
+        Ok(self
+            .items
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|stored| stored.item.blob_key == blob_key)
+            .count() as i64)
+    }
+
+    async fn take_read_once_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+    ) -> Result<Option<DbItem>, StorageError> {
+        let mut items = self.items.lock().unwrap();
+        let takeable = items.get(&id).is_some_and(|stored| {
+            stored.item.read_once && stored.item.pubkey == pubkey && !stored.is_expired(Utc::now())
+        });
+        Ok(takeable
+            .then(|| items.remove(&id))
+            .flatten()
+            .map(|stored| stored.item))
+    }
+
+    async fn acknowledge_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        now: DateTime<Utc>,
+        purge_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        match self.items.lock().unwrap().get_mut(&id) {
+            Some(stored) if stored.item.pubkey == pubkey && !stored.is_expired(now) => {
+                stored.delivered_at.get_or_insert(now);
+                stored.expires_at = Some(stored.expires_at.map_or(purge_at, |t| t.min(purge_at)));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn expired_items(
+        &self,
+        now: DateTime<Utc>,
+        created_before: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<DbItem>, StorageError> {
+        Ok(self
+            .items
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|stored| {
+                stored.is_expired(now)
+                    || created_before.is_some_and(|before| stored.item.created_at < before)
+            })
+            .take(limit as usize)
+            .map(|stored| stored.item.clone())
+            .collect())
+    }
+
+    async fn upsert_notification(
+        &self,
+        pubkey: &str,
+        telegram: &str,
+    ) -> Result<DbNotification, StorageError> {
+        let registration = DbNotification {
+            pubkey: pubkey.to_string(),
+            telegram: telegram.to_string(),
+            created_at: Utc::now(),
+        };
+        self.notifications
+            .lock()
+            .unwrap()
+            .insert(pubkey.to_string(), registration.clone());
+        Ok(registration)
+    }
+
+    async fn get_notification(&self, pubkey: &str) -> Result<Option<DbNotification>, StorageError> {
+        Ok(self.notifications.lock().unwrap().get(pubkey).cloned())
+    }
+
+    async fn get_mailbox_settings(&self, pubkey: &str) -> Result<MailboxSettings, StorageError> {
+        Ok(self
+            .mailbox_settings
+            .lock()
+            .unwrap()
+            .get(pubkey)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_mailbox_settings(
+        &self,
+        pubkey: &str,
+        settings: &MailboxSettings,
+    ) -> Result<(), StorageError> {
+        self.mailbox_settings
+            .lock()
+            .unwrap()
+            .insert(pubkey.to_string(), settings.clone());
+        Ok(())
+    }
+
+    async fn consume_capability(
+        &self,
+        id: Uuid,
+        _pubkey: &str,
+        max_uses: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let mut capability_uses = self.capability_uses.lock().unwrap();
+        let (uses, _) = capability_uses.entry(id).or_insert((0, expires_at));
+        if *uses >= max_uses {
+            return Ok(false);
+        }
+        *uses += 1;
+        Ok(true)
+    }
+
+    async fn release_capability(&self, id: Uuid) -> Result<(), StorageError> {
+        if let Some((uses, _)) = self.capability_uses.lock().unwrap().get_mut(&id) {
+            *uses = (*uses - 1).max(0);
+        }
+        Ok(())
+    }
+
+    async fn delete_expired_capabilities(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        let mut capability_uses = self.capability_uses.lock().unwrap();
+        let before = capability_uses.len();
+        capability_uses.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok((before - capability_uses.len()) as u64)
+    }
+
+    async fn unhashed_pubkeys(&self) -> Result<Vec<String>, StorageError> {
+        // Mailboxes in memory never predate hashed ids
+        Ok(Vec::new())
+    }
+
+    async fn rename_mailbox(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        for stored in self.items.lock().unwrap().values_mut() {
+            if stored.item.pubkey == from {
+                stored.item.pubkey = to.to_string();
+            }
+        }
+        for session in self.upload_sessions.lock().unwrap().values_mut() {
+            if session.pubkey == from {
+                session.pubkey = to.to_string();
+            }
+        }
+        let mut notifications = self.notifications.lock().unwrap();
+        if let Some(mut registration) = notifications.remove(from) {
+            registration.pubkey = to.to_string();
+            notifications.entry(to.to_string()).or_insert(registration);
+        }
+        let mut settings = self.mailbox_settings.lock().unwrap();
+        if let Some(old) = settings.remove(from) {
+            settings.entry(to.to_string()).or_insert(old);
+        }
+        Ok(())
+    }
+
+    async fn skip_unhashed_pubkey(&self, _pubkey: &str) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn inline_ciphertexts(&self, _limit: u32) -> Result<Vec<(Uuid, Vec<u8>)>, StorageError> {
+        // Items in memory never predate the blob store
+        Ok(Vec::new())
+    }
+
+    async fn set_blob_ref(&self, id: Uuid, blob_key: &str, size: i64) -> Result<(), StorageError> {
+        if let Some(stored) = self.items.lock().unwrap().get_mut(&id) {
+            stored.item.blob_key = blob_key.to_string();
+            stored.item.size = size;
+        }
+        Ok(())
+    }
+
+    async fn create_upload_session(
+        &self,
+        new_session: &NewUploadSession,
+    ) -> Result<DbUploadSession, StorageError> {
+        let now = Utc::now();
+        let session = DbUploadSession {
+            id: Uuid::new_v4(),
+            pubkey: new_session.pubkey.clone(),
+            labels: new_session.labels.clone().map(Json),
+            expires_in: new_session.expires_in,
+            read_once: new_session.read_once,
+            received: 0,
+            max_size: new_session.max_size,
+            capability: new_session.capability.clone().map(Json),
+            created_at: now,
+            updated_at: now,
+        };
+        self.upload_sessions
+            .lock()
+            .unwrap()
+            .insert(session.id, session.clone());
+        Ok(session)
+    }
+
+    async fn get_upload_session(&self, id: Uuid) -> Result<Option<DbUploadSession>, StorageError> {
+        Ok(self.upload_sessions.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn advance_upload_session(
+        &self,
+        id: Uuid,
+        from: i64,
+        to: i64,
+    ) -> Result<bool, StorageError> {
+        match self.upload_sessions.lock().unwrap().get_mut(&id) {
+            Some(session) if session.received == from => {
+                session.received = to;
+                session.updated_at = Utc::now();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_upload_session(&self, id: Uuid) -> Result<bool, StorageError> {
+        Ok(self.upload_sessions.lock().unwrap().remove(&id).is_some())
+    }
+
+    async fn stale_upload_sessions(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<Uuid>, StorageError> {
+        Ok(self
+            .upload_sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|session| session.updated_at < before)
+            .map(|session| session.id)
+            .collect())
+    }
+}
@@ -0,0 +1,252 @@
+pub mod memory;
+
+use crate::config::Config;
+use crate::db::{
+    DbItem, DbNotification, DbUploadSession, ItemMeta, MailboxSettings, NewItem, NewUploadSession,
+    PgStorage,
+};
+use crate::error::StorageError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub use memory::MemoryStorage;
+
+/// Position of the last item on a page: `(created_at, id)` of that item.
+pub type PageKey = (DateTime<Utc>, Uuid);
+
+/// Per-mailbox limits enforced by [`Storage::insert_item`]; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quota {
+    pub max_items: Option<i64>,
+    pub max_bytes: Option<i64>,
+}
+
+impl Quota {
+    /// The limits set by `MAILBOX_MAX_ITEMS` and `MAILBOX_MAX_BYTES`.
+    pub fn from_config(config: &Config) -> Self {
+        Quota {
+            max_items: config.mailbox_max_items,
+            max_bytes: config.mailbox_max_bytes,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_items.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Whether a mailbox currently holding `usage` can take an item of `size` bytes.
+    pub fn allows(&self, usage: Usage, size: i64) -> bool {
+        self.max_items.is_none_or(|max| usage.items < max)
+            && self.max_bytes.is_none_or(|max| usage.bytes + size <= max)
+    }
+}
+
+/// Unexpired items and ciphertext bytes stored for one mailbox.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub items: i64,
+    pub bytes: i64,
+}
+
+/// Which items [`Storage::list_items`] returns, by acknowledgement state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryFilter {
+    #[default]
+    All,
+    Delivered,
+    Undelivered,
+}
+
+impl DeliveryFilter {
+    /// Whether an item with the given `delivered_at` passes the filter.
+    pub fn matches(self, delivered_at: Option<DateTime<Utc>>) -> bool {
+        match self {
+            DeliveryFilter::All => true,
+            DeliveryFilter::Delivered => delivered_at.is_some(),
+            DeliveryFilter::Undelivered => delivered_at.is_none(),
+        }
+    }
+}
+
+/// Persistence backend for item metadata and notification registrations.
+///
+/// Handlers only talk to storage through this trait, so the router can run
+/// against PostgreSQL in production and an in-memory backend in tests.
+/// Ciphertext bodies live in a [`crate::blob::BlobStore`]; items only keep
+/// the blob key and size.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Insert an item unless its mailbox would exceed `quota`, failing with
+    /// [`StorageError::QuotaExceeded`]. The check is atomic with respect to
+    /// concurrent inserts for the same pubkey.
+    async fn insert_item(&self, item: &NewItem, quota: Quota) -> Result<DbItem, StorageError>;
+
+    /// Unexpired items and bytes currently stored for a pubkey.
+    async fn mailbox_usage(&self, pubkey: &str) -> Result<Usage, StorageError>;
+
+    /// List unexpired item metadata for a pubkey ordered by `created_at DESC, id DESC`,
+    /// starting strictly after `after` when given (keyset pagination).
+    async fn list_items(
+        &self,
+        pubkey: &str,
+        filter: DeliveryFilter,
+        after: Option<PageKey>,
+        limit: u32,
+    ) -> Result<Vec<ItemMeta>, StorageError>;
+
+    /// Look up an item; expired items are treated as missing.
+    async fn get_item(&self, id: Uuid) -> Result<Option<DbItem>, StorageError>;
+
+    /// Delete an item, returning whether it existed.
+    async fn delete_item(&self, id: Uuid) -> Result<bool, StorageError>;
+
+    /// Number of items whose ciphertext is the blob `blob_key`.
+    async fn count_blob_refs(&self, blob_key: &str) -> Result<i64, StorageError>;
+
+    /// Delete and return a read-once item in one atomic step, so only one of
+    /// several concurrent downloads gets it. `None` if the item is missing,
+    /// expired, not read-once or owned by another pubkey.
+    async fn take_read_once_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+    ) -> Result<Option<DbItem>, StorageError>;
+
+    /// Mark an unexpired item owned by `pubkey` as delivered at `now` and
+    /// bring its expiry forward to `purge_at`. Acknowledging again keeps the
+    /// first delivery time and the earlier expiry. Returns whether the item
+    /// was found.
+    async fn acknowledge_item(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        now: DateTime<Utc>,
+        purge_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError>;
+
+    /// Up to `limit` items whose `expires_at` is at or before `now`, or that
+    /// were created before `created_before` when a global max age is set.
+    async fn expired_items(
+        &self,
+        now: DateTime<Utc>,
+        created_before: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<DbItem>, StorageError>;
+
+    /// Register (or replace) the Telegram target for a pubkey.
+    async fn upsert_notification(
+        &self,
+        pubkey: &str,
+        telegram: &str,
+    ) -> Result<DbNotification, StorageError>;
+
+    async fn get_notification(&self, pubkey: &str) -> Result<Option<DbNotification>, StorageError>;
+
+    /// Settings of a mailbox; defaults if the owner never saved any.
+    async fn get_mailbox_settings(&self, pubkey: &str) -> Result<MailboxSettings, StorageError>;
+
+    async fn set_mailbox_settings(
+        &self,
+        pubkey: &str,
+        settings: &MailboxSettings,
+    ) -> Result<(), StorageError>;
+
+    /// Record one use of the upload capability `id` for `pubkey`, returning
+    /// false once it has been used `max_uses` times. The counter is kept
+    /// until `expires_at`.
+    async fn consume_capability(
+        &self,
+        id: Uuid,
+        pubkey: &str,
+        max_uses: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, StorageError>;
+
+    /// Give back one use of capability `id`, after the upload it was counted
+    /// for could not be stored.
+    async fn release_capability(&self, id: Uuid) -> Result<(), StorageError>;
+
+    /// Drop the use counters of capabilities expired at `now`.
+    async fn delete_expired_capabilities(&self, now: DateTime<Utc>) -> Result<u64, StorageError>;
+
+    /// Raw pubkeys stored by versions before [`crate::mailbox::storage_key`],
+    /// as recorded by the schema migration that introduced it, which have not
+    /// been rewritten or skipped yet.
+    async fn unhashed_pubkeys(&self) -> Result<Vec<String>, StorageError>;
+
+    /// Move everything stored for the raw pubkey `from` to mailbox `to` and
+    /// drop it from [`Storage::unhashed_pubkeys`]. Settings and notification
+    /// targets `to` already has are kept.
+    async fn rename_mailbox(&self, from: &str, to: &str) -> Result<(), StorageError>;
+
+    /// Drop a raw pubkey that cannot be rewritten from [`Storage::unhashed_pubkeys`],
+    /// leaving its rows alone.
+    async fn skip_unhashed_pubkey(&self, pubkey: &str) -> Result<(), StorageError>;
+
+    /// Up to `limit` legacy rows whose ciphertext is still stored inline.
+    async fn inline_ciphertexts(&self, limit: u32) -> Result<Vec<(Uuid, Vec<u8>)>, StorageError>;
+
+    /// Point a legacy row at its blob and drop the inline ciphertext.
+    async fn set_blob_ref(&self, id: Uuid, blob_key: &str, size: i64) -> Result<(), StorageError>;
+
+    /// Start a resumable upload with nothing received yet.
+    async fn create_upload_session(
+        &self,
+        session: &NewUploadSession,
+    ) -> Result<DbUploadSession, StorageError>;
+
+    async fn get_upload_session(&self, id: Uuid) -> Result<Option<DbUploadSession>, StorageError>;
+
+    /// Move `received` from `from` to `to` and touch `updated_at`. Returns
+    /// false if the session is gone or another chunk advanced it first.
+    async fn advance_upload_session(
+        &self,
+        id: Uuid,
+        from: i64,
+        to: i64,
+    ) -> Result<bool, StorageError>;
+
+    /// Delete a session, returning whether it existed.
+    async fn delete_upload_session(&self, id: Uuid) -> Result<bool, StorageError>;
+
+    /// IDs of sessions not updated since `before`.
+    async fn stale_upload_sessions(&self, before: DateTime<Utc>)
+    -> Result<Vec<Uuid>, StorageError>;
+}
+
+/// Open the storage backend selected by `DATABASE_URL` and run its migrations.
+///
+/// `memory:` selects the non-persistent in-memory backend (development only),
+/// `sqlite:` the SQLite backend (opt-in `sqlite` feature); anything else is
+/// treated as a PostgreSQL connection string.
+pub async fn connect(config: &Config) -> Result<Arc<dyn Storage>, StorageError> {
+    if config.database_url.starts_with("memory:") {
+        return Ok(Arc::new(MemoryStorage::new()));
+    }
+    #[cfg(feature = "sqlite")]
+    if config.database_url.starts_with("sqlite:") {
+        let storage = crate::db::sqlite::SqliteStorage::connect(&config.database_url).await?;
+        crate::db::sqlite::db_migrate(&storage).await?;
+        return Ok(Arc::new(storage));
+    }
+    #[cfg(not(feature = "sqlite"))]
+    if config.database_url.starts_with("sqlite:") {
+        return Err(StorageError::Config(
+            "SQLite DATABASE_URL needs a server built with --features sqlite".to_string(),
+        ));
+    }
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&config.database_url)
+        .await?;
+    crate::db::db_migrate(&pool).await?;
+    Ok(Arc::new(PgStorage::new(pool)))
+}
+
+#[cfg(test)]
+mod tests;
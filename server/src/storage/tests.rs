@@ -0,0 +1,321 @@
+use super::*;
+use crate::db::{MailboxSettings, NewItem, NewUploadSession};
+
+#[tokio::test]
+async fn test_memory_insert_get_delete() {
+    let storage = MemoryStorage::new();
+    let item = storage
+        .insert_item(&NewItem::new("pk", "abcd", 6), Quota::default())
+        .await
+        .unwrap();
+    let fetched = storage.get_item(item.id).await.unwrap().unwrap();
+    assert_eq!(fetched.pubkey, "pk");
+    assert_eq!(fetched.blob_key, "abcd");
+    assert_eq!(fetched.size, 6);
+    assert_eq!(storage.count_blob_refs("abcd").await.unwrap(), 1);
+    assert!(storage.delete_item(item.id).await.unwrap());
+    assert!(!storage.delete_item(item.id).await.unwrap());
+    assert_eq!(storage.count_blob_refs("abcd").await.unwrap(), 0);
+    assert!(storage.get_item(item.id).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_memory_list_pages() {
+    let storage = MemoryStorage::new();
+    for i in 0..5 {
+        storage
+            .insert_item(&NewItem::new("pk", "abcd", i), Quota::default())
+            .await
+            .unwrap();
+    }
+    storage
+        .insert_item(&NewItem::new("other", "abcd", 99), Quota::default())
+        .await
+        .unwrap();
+
+    let first = storage
+        .list_items("pk", DeliveryFilter::All, None, 3)
+        .await
+        .unwrap();
+    assert_eq!(first.len(), 3);
+    let last = first.last().unwrap();
+    let second = storage
+        .list_items(
+            "pk",
+            DeliveryFilter::All,
+            Some((last.created_at, last.id)),
+            3,
+        )
+        .await
+        .unwrap();
+    assert_eq!(second.len(), 2);
+
+    // Pages are disjoint and ordered newest first
+    let all: Vec<_> = first.iter().chain(second.iter()).collect();
+    for pair in all.windows(2) {
+        assert!((pair[0].created_at, pair[0].id) > (pair[1].created_at, pair[1].id));
+    }
+    // Sizes 0..5 were only stored for "pk"
+    assert!(all.iter().all(|item| item.size < 5));
+    assert_eq!(all.len(), 5);
+}
+
+#[tokio::test]
+async fn test_memory_notifications() {
+    let storage = MemoryStorage::new();
+    assert!(storage.get_notification("pk").await.unwrap().is_none());
+    storage.upsert_notification("pk", "@first").await.unwrap();
+    storage.upsert_notification("pk", "@second").await.unwrap();
+    let registration = storage.get_notification("pk").await.unwrap().unwrap();
+    assert_eq!(registration.telegram, "@second");
+}
+
+#[tokio::test]
+async fn test_memory_upload_sessions() {
+    let storage = MemoryStorage::new();
+    let session = storage
+        .create_upload_session(&NewUploadSession::new("pk"))
+        .await
+        .unwrap();
+    assert_eq!(session.received, 0);
+    assert!(
+        storage
+            .advance_upload_session(session.id, 0, 5)
+            .await
+            .unwrap()
+    );
+    // Advancing from a stale offset fails
+    assert!(
+        !storage
+            .advance_upload_session(session.id, 0, 5)
+            .await
+            .unwrap()
+    );
+    let fetched = storage
+        .get_upload_session(session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(fetched.received, 5);
+
+    assert!(
+        storage
+            .stale_upload_sessions(session.created_at)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let later = fetched.updated_at + chrono::Duration::seconds(1);
+    assert_eq!(
+        storage.stale_upload_sessions(later).await.unwrap(),
+        vec![session.id]
+    );
+    assert!(storage.delete_upload_session(session.id).await.unwrap());
+    assert!(!storage.delete_upload_session(session.id).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_memory_take_read_once_item() {
+    let storage = MemoryStorage::new();
+    let plain = storage
+        .insert_item(&NewItem::new("pk", "abcd", 4), Quota::default())
+        .await
+        .unwrap();
+    let mut new_item = NewItem::new("pk", "abcd", 4);
+    new_item.read_once = true;
+    let once = storage
+        .insert_item(&new_item, Quota::default())
+        .await
+        .unwrap();
+
+    assert!(
+        storage
+            .take_read_once_item(plain.id, "pk")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        storage
+            .take_read_once_item(once.id, "other")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let taken = storage
+        .take_read_once_item(once.id, "pk")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(taken.read_once);
+    assert!(
+        storage
+            .take_read_once_item(once.id, "pk")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(storage.get_item(once.id).await.unwrap().is_none());
+    assert!(storage.get_item(plain.id).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_memory_acknowledge_item() {
+    let storage = MemoryStorage::new();
+    let item = storage
+        .insert_item(&NewItem::new("pk", "abcd", 4), Quota::default())
+        .await
+        .unwrap();
+    storage
+        .insert_item(&NewItem::new("pk", "abcd", 4), Quota::default())
+        .await
+        .unwrap();
+    let now = Utc::now();
+    let purge_at = now + chrono::Duration::hours(1);
+    assert!(
+        !storage
+            .acknowledge_item(item.id, "other", now, purge_at)
+            .await
+            .unwrap()
+    );
+    assert!(
+        storage
+            .acknowledge_item(item.id, "pk", now, purge_at)
+            .await
+            .unwrap()
+    );
+    let delivered = storage
+        .list_items("pk", DeliveryFilter::Delivered, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].delivered_at, Some(now));
+    assert_eq!(delivered[0].expires_at, Some(purge_at));
+    assert_eq!(
+        storage
+            .list_items("pk", DeliveryFilter::Undelivered, None, 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        storage.expired_items(purge_at, None, 10).await.unwrap()[0].id,
+        item.id
+    );
+}
+
+#[tokio::test]
+async fn test_memory_insert_within_quota() {
+    let storage = MemoryStorage::new();
+    let quota = Quota {
+        max_items: Some(2),
+        max_bytes: Some(10),
+    };
+    storage
+        .insert_item(&NewItem::new("pk", "a", 6), quota)
+        .await
+        .unwrap();
+    // 6 + 5 bytes would exceed the byte quota
+    assert!(matches!(
+        storage
+            .insert_item(&NewItem::new("pk", "b", 5), quota)
+            .await,
+        Err(StorageError::QuotaExceeded)
+    ));
+    storage
+        .insert_item(&NewItem::new("pk", "b", 4), quota)
+        .await
+        .unwrap();
+    assert!(matches!(
+        storage
+            .insert_item(&NewItem::new("pk", "c", 0), quota)
+            .await,
+        Err(StorageError::QuotaExceeded)
+    ));
+    // Quotas are per mailbox
+    storage
+        .insert_item(&NewItem::new("other", "c", 10), quota)
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.mailbox_usage("pk").await.unwrap(),
+        Usage {
+            items: 2,
+            bytes: 10
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_memory_mailbox_settings() {
+    let storage = MemoryStorage::new();
+    assert_eq!(
+        storage.get_mailbox_settings("pk").await.unwrap(),
+        MailboxSettings::default()
+    );
+    let settings = MailboxSettings {
+        pow_difficulty: Some(12),
+        capability_required: true,
+    };
+    storage.set_mailbox_settings("pk", &settings).await.unwrap();
+    assert_eq!(storage.get_mailbox_settings("pk").await.unwrap(), settings);
+    assert_eq!(
+        storage.get_mailbox_settings("other").await.unwrap(),
+        MailboxSettings::default()
+    );
+}
+
+#[tokio::test]
+async fn test_memory_consume_capability() {
+    let storage = MemoryStorage::new();
+    let id = Uuid::new_v4();
+    let expires_at = Utc::now() + chrono::Duration::hours(1);
+    for _ in 0..2 {
+        assert!(
+            storage
+                .consume_capability(id, "pk", 2, expires_at)
+                .await
+                .unwrap()
+        );
+    }
+    assert!(
+        !storage
+            .consume_capability(id, "pk", 2, expires_at)
+            .await
+            .unwrap()
+    );
+    // A released use can be spent again
+    storage.release_capability(id).await.unwrap();
+    assert!(
+        storage
+            .consume_capability(id, "pk", 2, expires_at)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        storage
+            .delete_expired_capabilities(Utc::now())
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        storage
+            .delete_expired_capabilities(expires_at)
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[tokio::test]
+async fn test_sqlite_url_needs_the_feature() {
+    let mut config = crate::config::test_config();
+    config.database_url = "sqlite::memory:".to_string();
+    assert!(matches!(
+        connect(&config).await,
+        Err(StorageError::Config(_))
+    ));
+}
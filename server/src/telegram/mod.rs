@@ -0,0 +1,66 @@
+use crate::config::{AppState, Config};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Send a text message through the Telegram Bot API `sendMessage` method.
+pub async fn send_message(
+    client: &reqwest::Client,
+    config: &Config,
+    chat_id: &str,
+    text: &str,
+) -> Result<(), String> {
+    let token = config
+        .telegram_bot_token
+        .as_deref()
+        .ok_or_else(|| "Telegram bot token not configured".to_string())?;
+    let url = format!(
+        "{}/bot{}/sendMessage",
+        config.telegram_api_base_url.trim_end_matches('/'),
+        token
+    );
+    let resp = client
+        .post(&url)
+        .json(&json!({ "chat_id": chat_id, "text": text }))
+        .send()
+        .await
+        // The URL carries the bot token, so keep it out of the error
+        .map_err(|e| format!("Telegram request error: {}", e.without_url()))?;
+    if !resp.status().is_success() {
+        return Err(format!("Telegram API returned {}", resp.status()));
+    }
+    Ok(())
+}
+
+/// Notify the registered Telegram target (if any) that a new item arrived.
+/// Runs in the background so uploads never wait on the Bot API.
+pub fn dispatch_new_item(state: &AppState, pubkey: &str, item_id: Uuid) {
+    if state.config.telegram_bot_token.is_none() {
+        return;
+    }
+    let state = state.clone();
+    let pubkey = pubkey.to_string();
+    tokio::spawn(async move {
+        let registration = match state.storage.get_notification(&pubkey).await {
+            Ok(Some(r)) => r,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Failed to look up notification registration: {}", e);
+                return;
+            }
+        };
+        let text = format!("New deadrop item available: {}", item_id);
+        if let Err(e) = send_message(
+            &state.http_client,
+            &state.config,
+            &registration.telegram,
+            &text,
+        )
+        .await
+        {
+            eprintln!("Failed to send Telegram notification: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+pub(crate) mod tests;
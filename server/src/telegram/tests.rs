@@ -0,0 +1,63 @@
+use super::*;
+use axum::{Json, Router, extract::Path, routing::post};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+fn test_config(base_url: &str) -> Config {
+    let mut config = crate::config::test_config();
+    config.telegram_bot_token = Some("123:TESTTOKEN".to_string());
+    config.telegram_api_base_url = base_url.to_string();
+    config
+}
+
+/// Start a mock Bot API server that forwards every `sendMessage` call to a channel.
+pub(crate) async fn start_mock_bot_api() -> (String, mpsc::UnboundedReceiver<(String, Value)>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let app = Router::new().route(
+        "/{bot}/sendMessage",
+        post(move |Path(bot): Path<String>, Json(body): Json<Value>| {
+            let tx = tx.clone();
+            async move {
+                tx.send((bot, body)).unwrap();
+                Json(serde_json::json!({ "ok": true }))
+            }
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}", addr), rx)
+}
+
+#[tokio::test]
+async fn test_send_message_calls_bot_api() {
+    let (base_url, mut rx) = start_mock_bot_api().await;
+    let config = test_config(&base_url);
+    send_message(&reqwest::Client::new(), &config, "@someone", "hello")
+        .await
+        .unwrap();
+    let (bot, body) = rx.recv().await.unwrap();
+    assert_eq!(bot, "bot123:TESTTOKEN");
+    assert_eq!(body["chat_id"], "@someone");
+    assert_eq!(body["text"], "hello");
+}
+
+#[tokio::test]
+async fn test_send_message_without_token_fails() {
+    let mut config = test_config("http://127.0.0.1:9");
+    config.telegram_bot_token = None;
+    let result = send_message(&reqwest::Client::new(), &config, "@someone", "hello").await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_send_message_error_hides_token() {
+    // Nothing listens on the discard port, so the request fails to connect
+    let config = test_config("http://127.0.0.1:9");
+    let error = send_message(&reqwest::Client::new(), &config, "@someone", "hello")
+        .await
+        .unwrap_err();
+    assert!(error.starts_with("Telegram request error"));
+    assert!(!error.contains("TESTTOKEN"));
+}
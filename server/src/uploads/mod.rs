@@ -0,0 +1,119 @@
+//! Resumable uploads. Session bookkeeping lives in [`crate::storage::Storage`];
+//! the bytes received so far are kept in one file per session under the
+//! staging dir until the session is finalized into an item.
+
+use crate::blob;
+use crate::config::{AppState, Config};
+use crate::error::{BlobError, StorageError};
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::fs::TryLockError;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// File holding the bytes received for session `id`.
+pub fn session_path(config: &Config, id: Uuid) -> PathBuf {
+    blob::staging_dir(config).join(format!("{}.upload", id))
+}
+
+/// Create the (empty) file backing a new session.
+pub async fn create_session_file(config: &Config, id: Uuid) -> Result<(), BlobError> {
+    tokio::fs::create_dir_all(blob::staging_dir(config)).await?;
+    tokio::fs::File::create(session_path(config, id)).await?;
+    Ok(())
+}
+
+/// Exclusive claim on a session file, held while a chunk is written or the
+/// session is finalized so that concurrent requests for the same offset
+/// cannot interleave their writes. The OS releases it when the value is
+/// dropped, even if the process dies.
+pub struct SessionLock {
+    _file: std::fs::File,
+}
+
+/// Claim the file of session `id`, or `None` if another request holds it.
+pub async fn try_lock_session(config: &Config, id: Uuid) -> Result<Option<SessionLock>, BlobError> {
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(session_path(config, id))
+        .await?
+        .into_std()
+        .await;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(SessionLock { _file: file })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Write a chunk into the session file starting at `offset` and return the
+/// number of bytes written. Fails with [`BlobError::TooLarge`] as soon as the
+/// upload would grow past `max_bytes`.
+///
+/// Bytes from an interrupted chunk may remain past the acknowledged offset;
+/// they are overwritten by the retried chunk or truncated on finalize.
+pub async fn write_chunk<S, E>(
+    path: &Path,
+    offset: u64,
+    mut stream: S,
+    max_bytes: u64,
+) -> Result<u64, BlobError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut written = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| BlobError::Io(std::io::Error::other(e)))?;
+        written += chunk.len() as u64;
+        if offset + written > max_bytes {
+            return Err(BlobError::TooLarge);
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(written)
+}
+
+/// Delete sessions idle for longer than `UPLOAD_SESSION_TTL_SECONDS` along
+/// with their files, returning how many were removed.
+pub async fn collect_abandoned(state: &AppState) -> Result<u64, StorageError> {
+    let cutoff =
+        chrono::Utc::now() - chrono::Duration::seconds(state.config.upload_session_ttl_seconds);
+    let mut removed = 0;
+    for id in state.storage.stale_upload_sessions(cutoff).await? {
+        if state.storage.delete_upload_session(id).await? {
+            match tokio::fs::remove_file(session_path(&state.config, id)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(BlobError::Io(e).into()),
+            }
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Run [`collect_abandoned`] every `UPLOAD_GC_INTERVAL_SECONDS` in the background.
+pub fn spawn_gc(state: AppState) {
+    let interval = Duration::from_secs(state.config.upload_gc_interval_seconds);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match collect_abandoned(&state).await {
+                Ok(0) => {}
+                Ok(n) => println!("Removed {} abandoned upload sessions.", n),
+                Err(e) => eprintln!("Upload session cleanup failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,84 @@
+use super::*;
+use crate::config::test_config;
+use crate::db::NewUploadSession;
+
+fn test_state(upload_session_ttl_seconds: i64) -> AppState {
+    let mut config = test_config();
+    config.upload_staging_dir = Some(
+        std::env::temp_dir()
+            .join(format!("deadrop-uploads-{}", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned(),
+    );
+    config.upload_session_ttl_seconds = upload_session_ttl_seconds;
+    crate::config::test_state(config)
+}
+
+fn chunks(data: &'static [u8]) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Unpin {
+    futures_util::stream::iter(data.chunks(4).map(|c| Ok(Bytes::from_static(c))))
+}
+
+#[tokio::test]
+async fn test_write_chunk_at_offset_and_limit() {
+    let state = test_state(60);
+    let session = state
+        .storage
+        .create_upload_session(&NewUploadSession::new("pk"))
+        .await
+        .unwrap();
+    create_session_file(&state.config, session.id)
+        .await
+        .unwrap();
+    let path = session_path(&state.config, session.id);
+
+    assert_eq!(
+        write_chunk(&path, 0, chunks(b"hello "), 16).await.unwrap(),
+        6
+    );
+    // A retried chunk overwrites whatever an interrupted attempt left behind
+    assert_eq!(
+        write_chunk(&path, 6, chunks(b"wxyzwxyz"), 16)
+            .await
+            .unwrap(),
+        8
+    );
+    assert_eq!(
+        write_chunk(&path, 6, chunks(b"world"), 16).await.unwrap(),
+        5
+    );
+    assert!(matches!(
+        write_chunk(&path, 11, chunks(b"too much data"), 16).await,
+        Err(BlobError::TooLarge)
+    ));
+
+    let staged = blob::StagedBlob::from_file(path, 11).await.unwrap();
+    assert_eq!(staged.key(), blob::content_key(b"hello world"));
+}
+
+#[tokio::test]
+async fn test_collect_abandoned_removes_sessions_and_files() {
+    let state = test_state(0);
+    let session = state
+        .storage
+        .create_upload_session(&NewUploadSession::new("pk"))
+        .await
+        .unwrap();
+    create_session_file(&state.config, session.id)
+        .await
+        .unwrap();
+    let path = session_path(&state.config, session.id);
+    assert!(path.exists());
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert_eq!(collect_abandoned(&state).await.unwrap(), 1);
+    assert!(!path.exists());
+    assert!(
+        state
+            .storage
+            .get_upload_session(session.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(collect_abandoned(&state).await.unwrap(), 0);
+}